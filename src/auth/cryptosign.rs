@@ -0,0 +1,100 @@
+//! Implements the signatures used by WAMP-Cryptosign, which authenticates sessions with Ed25519
+//! keys.
+use hex;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{self, Ed25519KeyPair};
+use untrusted::Input;
+
+/// The length of the random challenge sent by the router, in bytes.
+const CHALLENGE_LEN: usize = 32;
+
+/// The length of an Ed25519 signature, in bytes.
+const SIGNATURE_LEN: usize = 64;
+
+/// Generates a random, hex encoded challenge.
+pub fn challenge() -> String {
+    let mut challenge = [0u8; CHALLENGE_LEN];
+    SystemRandom::new()
+        .fill(&mut challenge)
+        .expect("Could not generate a random challenge");
+    hex::encode(&challenge[..])
+}
+
+/// Returns the hex encoded public key that belongs to the given private key, or `None` if the
+/// private key is not a 32 byte Ed25519 seed.
+pub fn public_key(private_key: &[u8]) -> Option<String> {
+    Ed25519KeyPair::from_seed_unchecked(Input::from(private_key))
+        .ok()
+        .map(|key_pair| hex::encode(key_pair.public_key_bytes()))
+}
+
+/// Signs a hex encoded challenge with the given private key.
+///
+/// As the specification requires, the signature is the hex encoded Ed25519 signature followed by
+/// the challenge itself.
+pub fn sign(private_key: &[u8], challenge: &str) -> Option<String> {
+    let key_pair = match Ed25519KeyPair::from_seed_unchecked(Input::from(private_key)) {
+        Ok(key_pair) => key_pair,
+        Err(_) => return None,
+    };
+    let challenge = match hex::decode(challenge) {
+        Ok(challenge) => challenge,
+        Err(_) => return None,
+    };
+    let mut signed = key_pair.sign(&challenge).as_ref().to_vec();
+    signed.extend_from_slice(&challenge);
+    Some(hex::encode(&signed))
+}
+
+/// Checks that a signature was made over the challenge by the owner of the given public key.
+pub fn verify(public_key: &str, challenge: &str, signature: &str) -> bool {
+    let (public_key, challenge, signature) = match (
+        hex::decode(public_key),
+        hex::decode(challenge),
+        hex::decode(signature),
+    ) {
+        (Ok(public_key), Ok(challenge), Ok(signature)) => (public_key, challenge, signature),
+        _ => return false,
+    };
+    if signature.len() < SIGNATURE_LEN || signature[SIGNATURE_LEN..] != challenge[..] {
+        return false;
+    }
+    signature::verify(
+        &signature::ED25519,
+        Input::from(&public_key),
+        Input::from(&challenge),
+        Input::from(&signature[..SIGNATURE_LEN]),
+    ).is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{challenge, public_key, sign, verify};
+    use hex;
+
+    const PRIVATE_KEY: &str = "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb";
+    const PUBLIC_KEY: &str = "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c";
+
+    #[test]
+    fn signing() {
+        let private_key = hex::decode(PRIVATE_KEY).unwrap();
+        assert_eq!(public_key(&private_key).unwrap(), PUBLIC_KEY);
+        assert_eq!(
+            sign(&private_key, "72").unwrap(),
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+             085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c0072"
+        );
+        assert!(public_key(&private_key[1..]).is_none());
+    }
+
+    #[test]
+    fn verifying() {
+        let private_key = hex::decode(PRIVATE_KEY).unwrap();
+        let challenge = challenge();
+        let signature = sign(&private_key, &challenge).unwrap();
+        assert!(verify(PUBLIC_KEY, &challenge, &signature));
+        assert!(!verify(PUBLIC_KEY, &super::challenge(), &signature));
+        assert!(!verify(PUBLIC_KEY, &challenge, &signature[..128]));
+        assert!(!verify(&PUBLIC_KEY.replace("3d", "3e"), &challenge, &signature));
+    }
+}
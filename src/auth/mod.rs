@@ -0,0 +1,22 @@
+//! Contains the cryptography shared by the client and the router for the authentication methods
+//! of the advanced profile.
+pub mod cryptosign;
+pub mod scram;
+pub mod wampcra;
+
+use base64;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Generates a random, base64 encoded nonce for use in a challenge.
+pub fn nonce() -> String {
+    base64::encode(&random_bytes(16))
+}
+
+/// Generates the given number of cryptographically secure random bytes.
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Could not generate random bytes");
+    bytes
+}
@@ -0,0 +1,153 @@
+//! Implements the proofs used by WAMP-SCRAM, the salted challenge-response authentication method.
+//!
+//! Only the PBKDF2 key derivation function is supported.  The router only needs to store the keys
+//! derived from a password, so a leaked credential store can not be used to impersonate a user.
+use base64;
+use ring::{constant_time, digest, hmac, pbkdf2};
+
+/// The name of the key derivation function sent in the challenge.
+pub const KDF_PBKDF2: &str = "pbkdf2";
+
+/// The number of PBKDF2 iterations used when adding a user to the router.
+pub const DEFAULT_ITERATIONS: u32 = 4096;
+
+/// The base64 encoded GS2 header, which says that channel binding is not used.
+const CHANNEL_BINDING: &str = "biws";
+
+const KEY_LEN: usize = 32;
+
+/// The keys a router stores for a user in place of their password.
+#[derive(Clone)]
+pub struct StoredKeys {
+    pub salt: String,
+    pub iterations: u32,
+    stored_key: Vec<u8>,
+    server_key: Vec<u8>,
+}
+
+impl StoredKeys {
+    /// Derives the keys for the given password, salt and number of iterations.
+    pub fn derive(password: &str, salt: &[u8], iterations: u32) -> StoredKeys {
+        let salted_password = salted_password(password, salt, iterations);
+        let client_key = hmac_sha256(&salted_password, b"Client Key");
+        StoredKeys {
+            salt: base64::encode(salt),
+            iterations: iterations,
+            stored_key: digest::digest(&digest::SHA256, &client_key).as_ref().to_vec(),
+            server_key: hmac_sha256(&salted_password, b"Server Key"),
+        }
+    }
+
+    /// Checks the base64 encoded proof a client sent for the given auth message, without leaking
+    /// timing information.
+    pub fn verify(&self, auth_message: &str, proof: &str) -> bool {
+        let proof = match base64::decode(proof) {
+            Ok(ref proof) if proof.len() == KEY_LEN => proof.clone(),
+            _ => return false,
+        };
+        let client_signature = hmac_sha256(&self.stored_key, auth_message.as_bytes());
+        let client_key = xor(&proof, &client_signature);
+        constant_time::verify_slices_are_equal(
+            digest::digest(&digest::SHA256, &client_key).as_ref(),
+            &self.stored_key,
+        ).is_ok()
+    }
+
+    /// Computes the base64 encoded signature that proves to the client that the router knows its
+    /// keys.
+    pub fn server_signature(&self, auth_message: &str) -> String {
+        base64::encode(&hmac_sha256(&self.server_key, auth_message.as_bytes()))
+    }
+}
+
+/// Derives the salted password from which all other keys are computed.
+pub fn salted_password(password: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut salted_password = vec![0u8; KEY_LEN];
+    pbkdf2::derive(
+        &digest::SHA256,
+        iterations,
+        salt,
+        password.as_bytes(),
+        &mut salted_password,
+    );
+    salted_password
+}
+
+/// Builds the message that both proofs are computed over, from the client's nonce and the
+/// combined nonce, salt and iterations the router sent in its challenge.
+pub fn auth_message(
+    authid: &str,
+    client_nonce: &str,
+    nonce: &str,
+    salt: &str,
+    iterations: u32,
+) -> String {
+    let authid = authid.replace("=", "=3D").replace(",", "=2C");
+    format!(
+        "n={},r={},r={},s={},i={},c={},r={}",
+        authid, client_nonce, nonce, salt, iterations, CHANNEL_BINDING, nonce
+    )
+}
+
+/// Computes the base64 encoded proof the client sends in response to the challenge.
+pub fn client_proof(salted_password: &[u8], auth_message: &str) -> String {
+    let client_key = hmac_sha256(salted_password, b"Client Key");
+    let stored_key = digest::digest(&digest::SHA256, &client_key);
+    let client_signature = hmac_sha256(stored_key.as_ref(), auth_message.as_bytes());
+    base64::encode(&xor(&client_key, &client_signature))
+}
+
+/// Computes the signature the client expects the router to send when it is welcomed.
+pub fn server_signature(salted_password: &[u8], auth_message: &str) -> String {
+    let server_key = hmac_sha256(salted_password, b"Server Key");
+    base64::encode(&hmac_sha256(&server_key, auth_message.as_bytes()))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::SigningKey::new(&digest::SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+fn xor(left: &[u8], right: &[u8]) -> Vec<u8> {
+    left.iter().zip(right).map(|(l, r)| l ^ r).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{auth_message, client_proof, salted_password, server_signature, StoredKeys};
+    use base64;
+
+    const CLIENT_NONCE: &str = "rOprNGfwEbeRWgbNEkqO";
+    const NONCE: &str = "rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0";
+    const SALT: &str = "W22ZaJ0SNY7soEsUEjb6gQ==";
+
+    #[test]
+    fn computing_proofs() {
+        let salt = base64::decode(SALT).unwrap();
+        let salted_password = salted_password("pencil", &salt, 4096);
+        let auth_message = auth_message("user", CLIENT_NONCE, NONCE, SALT, 4096);
+        assert_eq!(
+            client_proof(&salted_password, &auth_message),
+            "dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert_eq!(
+            server_signature(&salted_password, &auth_message),
+            "6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="
+        );
+    }
+
+    #[test]
+    fn verifying_proofs() {
+        let salt = base64::decode(SALT).unwrap();
+        let keys = StoredKeys::derive("pencil", &salt, 4096);
+        assert_eq!(keys.salt, SALT);
+        let auth_message = auth_message("user", CLIENT_NONCE, NONCE, SALT, 4096);
+        assert!(keys.verify(&auth_message, "dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="));
+        assert!(!keys.verify(&auth_message, "dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVA="));
+        assert!(!keys.verify(&auth_message, "dHzbZapWIk4jUhN+"));
+        assert_eq!(
+            keys.server_signature(&auth_message),
+            "6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="
+        );
+    }
+}
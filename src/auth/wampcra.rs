@@ -0,0 +1,60 @@
+//! Implements the signatures used by WAMP-CRA, the challenge-response authentication method.
+use base64;
+use ring::{constant_time, digest, hmac, pbkdf2};
+
+/// The number of PBKDF2 iterations used for salted secrets if the router does not specify one.
+pub const DEFAULT_ITERATIONS: u32 = 1000;
+
+/// The length of the derived key used for salted secrets if the router does not specify one.
+pub const DEFAULT_KEYLEN: usize = 32;
+
+/// Signs a challenge with the given secret.
+///
+/// The signature is the base64 encoded HMAC-SHA256 of the challenge, keyed with the secret.
+pub fn sign(secret: &str, challenge: &str) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+    base64::encode(hmac::sign(&key, challenge.as_bytes()).as_ref())
+}
+
+/// Checks that a signature matches the challenge, without leaking timing information.
+pub fn verify(secret: &str, challenge: &str, signature: &str) -> bool {
+    constant_time::verify_slices_are_equal(sign(secret, challenge).as_bytes(), signature.as_bytes())
+        .is_ok()
+}
+
+/// Derives the key that is used in place of a secret when the credentials are salted.
+///
+/// The key is the base64 encoded PBKDF2-HMAC-SHA256 of the secret.
+pub fn derive_key(secret: &str, salt: &str, iterations: u32, keylen: usize) -> String {
+    let mut key = vec![0u8; keylen];
+    pbkdf2::derive(
+        &digest::SHA256,
+        iterations,
+        salt.as_bytes(),
+        secret.as_bytes(),
+        &mut key,
+    );
+    base64::encode(&key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{derive_key, sign, verify};
+
+    #[test]
+    fn signing() {
+        let signature = sign("Jefe", "what do ya want for nothing?");
+        assert_eq!(signature, "W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM=");
+        assert!(verify("Jefe", "what do ya want for nothing?", &signature));
+        assert!(!verify("Jeff", "what do ya want for nothing?", &signature));
+    }
+
+    #[test]
+    fn deriving_keys() {
+        assert_eq!(derive_key("secret", "salt123", 100, 16), "EX1Y3Q9BMM2oGLxsGqDgFQ==");
+        assert_eq!(
+            derive_key("passwd", "salt", 1, 64),
+            "VawEblbjCJ/sFpHCJUS2BflBhSFt3gRl5oudV8INrLxJypzM8Xm2RZkWZLOdd+8xfHG4RbHjC9UJESBB06GXgw=="
+        );
+    }
+}
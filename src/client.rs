@@ -1,34 +1,60 @@
 use ws::{connect, CloseCode, Error as WSError, ErrorKind as WSErrorKind, Handler, Handshake,
-         Message as WSMessage, Request, Result as WSResult, Sender};
+         Message as WSMessage, Request, Result as WSResult};
 
 use ws::util::Token;
 
 use eventual::{Complete, Future};
-use messages::{CallOptions, ClientRoles, Dict, ErrorDetails, ErrorType, HelloDetails,
-               InvocationDetails, List, MatchingPolicy, Message, PublishOptions, Reason,
-               RegisterOptions, ResultDetails, SubscribeOptions, WelcomeDetails, YieldOptions, URI};
-use rmp_serde::Deserializer as RMPDeserializer;
-use rmp_serde::Serializer;
-use serde::{Deserialize, Serialize};
-use serde_json;
+use messages::{CallOptions, CancelMode, CancelOptions, ClientRoles, Dict, ErrorDetails,
+               ErrorType, EventDetails, HelloDetails, InterruptOptions, InvocationDetails, List,
+               MatchingPolicy, Message, PublishOptions, Reason, RegisterOptions, ResultDetails,
+               RouterRoles, SubscribeOptions, WelcomeDetails, YieldOptions, URI};
 use std::collections::HashMap;
 use std::fmt;
-use std::io::Cursor;
+use std::io;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Sender as CHSender};
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
 use std::time::Duration;
+use auth::{self, cryptosign, scram, wampcra};
+use base64;
+use payload::{PayloadKey, PPT_CIPHER, PPT_SCHEME, PPT_SERIALIZER};
+use serializer::{self, JsonSerializer, Serializer};
+use transport::rawsocket::{self, RawSocketFormat, Socket};
+use transport::{self, Transport};
 use url::Url;
-use utils::StructMapWriter;
-use {CallError, CallResult, Error, ErrorKind, WampResult, ID};
+use {require_feature, CallError, CallResult, Error, ErrorKind, Value, WampResult, ID};
 
 const CONNECTION_TIMEOUT: Token = Token(124);
 
+#[derive(Clone)]
 pub struct Connection {
     // sender: Sender,
     // receiver: client::Receiver<stream::WebSocketStream>,
     realm: URI,
     url: String,
+    authid: Option<String>,
+    credentials: Vec<Credential>,
+    serializer: Option<&'static Serializer>,
+}
+
+/// A secret the client can use to answer a challenge from the router.
+#[derive(Clone)]
+enum Credential {
+    WampCra(String),
+    Ticket(String),
+    Cryptosign {
+        private_key: Vec<u8>,
+        public_key: String,
+    },
+    Scram {
+        authid: String,
+        password: String,
+        client_nonce: Option<String>,
+    },
 }
 
 pub struct Subscription {
@@ -42,17 +68,49 @@ pub struct Registration {
 }
 
 struct SubscriptionCallbackWrapper {
-    callback: Box<FnMut(List, Dict)>,
+    callback: Box<FnMut(List, Dict, EventDetails)>,
 }
 
 struct RegistrationCallbackWrapper {
-    callback: Callback,
+    callback: RegistrationCallback,
+}
+
+struct ProgressCallbackWrapper {
+    callback: Box<FnMut(List, Dict)>,
 }
 
+enum RegistrationCallback {
+    Immediate(Callback),
+    Deferred(DeferredCallback),
+}
+
+/// A callback for a procedure whose result is returned as soon as it is called.  Interrupts only
+/// reach procedures registered with a `DeferredCallback`; an interrupt for an immediate callback
+/// arrives after its result has been sent, and is ignored.
 pub type Callback = Box<FnMut(List, Dict) -> CallResult<(Option<List>, Option<Dict>)>>;
 
-static WAMP_JSON: &'static str = "wamp.2.json";
-static WAMP_MSGPACK: &'static str = "wamp.2.msgpack";
+/// A callback for a procedure whose result is sent through the given `Invocation` rather than
+/// returned, so that it can yield progressive results or finish on another thread.
+pub type DeferredCallback = Box<FnMut(List, Dict, Invocation)>;
+
+/// A handle to a single invocation of a procedure registered with `Client::register_deferred`.
+///
+/// The handle may be moved to another thread.  It must eventually be completed with
+/// `complete()`, or the caller will never receive a result.  If the caller cancels the call,
+/// the invocation is interrupted.  When the caller cancels with `CancelMode::Kill`, the
+/// invocation should still be completed, usually with an error, and the caller receives that
+/// result.  When it cancels with `CancelMode::KillNoWait`, the call has already been answered
+/// and any further results are discarded.
+pub struct Invocation {
+    connection_info: Arc<Mutex<ConnectionInfo>>,
+    request_id: ID,
+    receive_progress: bool,
+    interrupted: Arc<AtomicBool>,
+    caller: Option<ID>,
+    caller_authid: Option<String>,
+    caller_authrole: Option<String>,
+    ppt_keyid: Option<String>,
+}
 
 #[derive(PartialEq, Debug)]
 enum ConnectionState {
@@ -76,6 +134,10 @@ unsafe impl<'a> Send for RegistrationCallbackWrapper {}
 
 unsafe impl<'a> Sync for RegistrationCallbackWrapper {}
 
+unsafe impl<'a> Send for ProgressCallbackWrapper {}
+
+unsafe impl<'a> Sync for ProgressCallbackWrapper {}
+
 pub struct Client {
     connection_info: Arc<Mutex<ConnectionInfo>>,
     max_session_id: ID,
@@ -84,12 +146,16 @@ pub struct Client {
 pub struct ConnectionHandler {
     connection_info: Arc<Mutex<ConnectionInfo>>,
     realm: URI,
+    authid: Option<String>,
+    credentials: Vec<Credential>,
+    challenge: Option<Dict>,
+    serializer: Option<&'static Serializer>,
     state_transmission: CHSender<ConnectionResult>,
 }
 
 struct ConnectionInfo {
     connection_state: ConnectionState,
-    sender: Sender,
+    sender: Box<Transport>,
     subscription_requests: HashMap<
         ID,
         (
@@ -100,8 +166,10 @@ struct ConnectionInfo {
     >,
     unsubscription_requests: HashMap<ID, (Complete<(), CallError>, ID)>,
     subscriptions: HashMap<ID, SubscriptionCallbackWrapper>,
-    registrations: HashMap<ID, RegistrationCallbackWrapper>,
+    registrations: HashMap<ID, Arc<Mutex<RegistrationCallbackWrapper>>>,
     call_requests: HashMap<ID, Complete<(List, Dict), CallError>>,
+    call_progress: HashMap<ID, ProgressCallbackWrapper>,
+    invocations: HashMap<ID, Arc<AtomicBool>>,
     registration_requests: HashMap<
         ID,
         (
@@ -111,10 +179,12 @@ struct ConnectionInfo {
         ),
     >,
     unregistration_requests: HashMap<ID, (Complete<(), CallError>, ID)>,
-    protocol: String,
+    serializer: &'static Serializer,
     publish_requests: HashMap<ID, Complete<ID, CallError>>,
     shutdown_complete: Option<Complete<(), CallError>>,
     session_id: ID,
+    realm: URI,
+    welcome_details: WelcomeDetails,
 }
 
 trait MessageSender {
@@ -123,31 +193,145 @@ trait MessageSender {
 
 impl MessageSender for ConnectionInfo {
     fn send_message(&self, message: Message) -> WampResult<()> {
-        debug!("Sending message {:?} via {}", message, self.protocol);
-        let send_result = if self.protocol == WAMP_JSON {
-            send_message_json(&self.sender, &message)
-        } else {
-            send_message_msgpack(&self.sender, &message)
-        };
-        match send_result {
+        debug!("Sending message {:?} via {}", message, self.serializer.protocol());
+        match self.sender.send(self.serializer.serialize(&message)) {
             Ok(()) => Ok(()),
             Err(e) => Err(Error::new(ErrorKind::WSError(e))),
         }
     }
 }
 
-fn send_message_json(sender: &Sender, message: &Message) -> WSResult<()> {
-    // Send the message
-    sender.send(WSMessage::Text(serde_json::to_string(message).unwrap()))
+fn invocation_response(
+    request_id: ID,
+    result: CallResult<(Option<List>, Option<Dict>)>,
+) -> Message {
+    match result {
+        Ok((rargs, rkwargs)) => Message::Yield(request_id, YieldOptions::new(), rargs, rkwargs),
+        Err(error) => {
+            let (reason, args, kwargs) = error.into_tuple();
+            Message::Error(
+                ErrorType::Invocation,
+                request_id,
+                HashMap::new(),
+                reason,
+                args,
+                kwargs,
+            )
+        }
+    }
+}
+
+impl Credential {
+    fn authmethod(&self) -> &'static str {
+        match *self {
+            Credential::WampCra(_) => "wampcra",
+            Credential::Ticket(_) => "ticket",
+            Credential::Cryptosign { .. } => "cryptosign",
+            Credential::Scram { .. } => "wamp-scram",
+        }
+    }
+
+    /// Computes the signature that answers a challenge from the router.
+    fn sign(&self, extra: &Dict) -> Option<String> {
+        match *self {
+            Credential::WampCra(ref secret) => {
+                let challenge = match extra.get("challenge") {
+                    Some(&Value::String(ref challenge)) => challenge,
+                    _ => return None,
+                };
+                match extra.get("salt") {
+                    Some(&Value::String(ref salt)) => {
+                        let iterations = get_unsigned(extra, "iterations")
+                            .map_or(wampcra::DEFAULT_ITERATIONS, |i| i as u32);
+                        let keylen = get_unsigned(extra, "keylen")
+                            .map_or(wampcra::DEFAULT_KEYLEN, |k| k as usize);
+                        let key = wampcra::derive_key(secret, salt, iterations, keylen);
+                        Some(wampcra::sign(&key, challenge))
+                    }
+                    _ => Some(wampcra::sign(secret, challenge)),
+                }
+            }
+            Credential::Ticket(ref ticket) => Some(ticket.clone()),
+            Credential::Cryptosign {
+                ref private_key, ..
+            } => match extra.get("challenge") {
+                Some(&Value::String(ref challenge)) => cryptosign::sign(private_key, challenge),
+                _ => None,
+            },
+            Credential::Scram { .. } => self.scram_keys(extra)
+                .map(|(salted_password, auth_message)| {
+                    scram::client_proof(&salted_password, &auth_message)
+                }),
+        }
+    }
+
+    /// Checks that the router proved it knows our credentials when it welcomed us.  A router that
+    /// welcomes us with WAMP-SCRAM without challenging us first has proved nothing.
+    fn verify_welcome(&self, challenge: Option<&Dict>, details: &WelcomeDetails) -> bool {
+        match *self {
+            Credential::Scram { .. } => {
+                let challenge = match challenge {
+                    Some(challenge) => challenge,
+                    None => return false,
+                };
+                let signature = match details
+                    .authextra
+                    .as_ref()
+                    .and_then(|authextra| authextra.get("scram_server_signature"))
+                {
+                    Some(&Value::String(ref signature)) => signature,
+                    _ => return false,
+                };
+                self.scram_keys(challenge)
+                    .map_or(false, |(salted_password, auth_message)| {
+                        scram::server_signature(&salted_password, &auth_message) == *signature
+                    })
+            }
+            _ => true,
+        }
+    }
+
+    /// Derives the salted password and auth message for a WAMP-SCRAM challenge.
+    fn scram_keys(&self, challenge: &Dict) -> Option<(Vec<u8>, String)> {
+        let (authid, password, client_nonce) = match *self {
+            Credential::Scram {
+                ref authid,
+                ref password,
+                client_nonce: Some(ref client_nonce),
+            } => (authid, password, client_nonce),
+            _ => return None,
+        };
+        let nonce = match challenge.get("nonce") {
+            Some(&Value::String(ref nonce)) if nonce.starts_with(&client_nonce[..]) => nonce,
+            _ => return None,
+        };
+        let salt = match challenge.get("salt") {
+            Some(&Value::String(ref salt)) => salt,
+            _ => return None,
+        };
+        match challenge.get("kdf") {
+            Some(&Value::String(ref kdf)) if kdf != scram::KDF_PBKDF2 => return None,
+            _ => {}
+        }
+        let iterations = match get_unsigned(challenge, "iterations") {
+            Some(iterations) => iterations as u32,
+            None => return None,
+        };
+        let salted_password = match base64::decode(salt) {
+            Ok(salt) => scram::salted_password(password, &salt, iterations),
+            Err(_) => return None,
+        };
+        let auth_message = scram::auth_message(authid, client_nonce, nonce, salt, iterations);
+        Some((salted_password, auth_message))
+    }
 }
 
-fn send_message_msgpack(sender: &Sender, message: &Message) -> WSResult<()> {
-    // Send the message
-    let mut buf: Vec<u8> = Vec::new();
-    message
-        .serialize(&mut Serializer::with(&mut buf, StructMapWriter))
-        .unwrap();
-    sender.send(WSMessage::Binary(buf))
+fn get_unsigned(dict: &Dict, key: &str) -> Option<u64> {
+    match dict.get(key) {
+        Some(&Value::UnsignedInteger(value)) => Some(value),
+        Some(&Value::Integer(value)) if value >= 0 => Some(value as u64),
+        _ => None,
+    }
 }
 
 impl Connection {
@@ -155,40 +339,98 @@ impl Connection {
         Connection {
             realm: URI::new(realm),
             url: url.to_string(),
+            authid: None,
+            credentials: Vec::new(),
+            serializer: None,
+        }
+    }
+
+    /// Uses only the serializer announced by the given WebSocket subprotocol, such as
+    /// `wamp.2.cbor`, instead of letting the router pick one.
+    pub fn set_serializer(&mut self, protocol: &str) -> WampResult<()> {
+        match serializer::from_protocol(protocol) {
+            Some(serializer) => {
+                self.serializer = Some(serializer);
+                Ok(())
+            }
+            None => Err(Error::new(ErrorKind::InvalidState("Unknown serializer"))),
         }
     }
 
+    /// Authenticates using WAMP-CRA, answering the router's challenge with the given secret.
+    pub fn set_wampcra_credentials(&mut self, authid: &str, secret: &str) {
+        self.authid = Some(authid.to_string());
+        self.set_credential(Credential::WampCra(secret.to_string()));
+    }
+
+    /// Authenticates by presenting the given ticket, such as a token issued by a login service.
+    pub fn set_ticket_credentials(&mut self, authid: &str, ticket: &str) {
+        self.authid = Some(authid.to_string());
+        self.set_credential(Credential::Ticket(ticket.to_string()));
+    }
+
+    /// Authenticates using WAMP-Cryptosign, answering the router's challenge by signing it with
+    /// the given 32 byte Ed25519 private key.  The router identifies the session by the
+    /// corresponding public key.
+    pub fn set_cryptosign_credentials(&mut self, private_key: &[u8]) -> WampResult<()> {
+        let public_key = match cryptosign::public_key(private_key) {
+            Some(public_key) => public_key,
+            None => return Err(Error::new(ErrorKind::MalformedData)),
+        };
+        self.set_credential(Credential::Cryptosign {
+            private_key: private_key.to_vec(),
+            public_key: public_key,
+        });
+        Ok(())
+    }
+
+    /// Authenticates using WAMP-SCRAM, proving to the router that we know the password without
+    /// sending it.
+    pub fn set_scram_credentials(&mut self, authid: &str, password: &str) {
+        self.authid = Some(authid.to_string());
+        self.set_credential(Credential::Scram {
+            authid: authid.to_string(),
+            password: password.to_string(),
+            client_nonce: None,
+        });
+    }
+
+    fn set_credential(&mut self, credential: Credential) {
+        self.credentials
+            .retain(|existing| existing.authmethod() != credential.authmethod());
+        self.credentials.push(credential);
+    }
+
+    /// Connects to the router.  URLs with the `ws` scheme connect using a WebSocket, and URLs
+    /// with the `rs` scheme, such as `rs://127.0.0.1:8081`, connect using RawSocket over TCP.
+    /// On Unix, URLs with the `unix` scheme, such as `unix:///run/wamp.sock`, connect using
+    /// RawSocket over a Unix domain socket.
     pub fn connect(&self) -> WampResult<Client> {
+        let url = try!(Url::parse(&self.url).map_err(|e| Error::new(ErrorKind::URLError(e))));
         let (tx, rx) = channel();
+        match url.scheme() {
+            "rs" => try!(self.connect_rawsocket(&url, tx)),
+            #[cfg(unix)]
+            "unix" => try!(self.connect_unix(&url, tx)),
+            _ => self.connect_websocket(tx),
+        }
+        let info = try!(rx.recv().unwrap());
+        Ok(Client {
+            connection_info: info,
+            max_session_id: 0,
+        })
+    }
+
+    fn connect_websocket(&self, tx: CHSender<ConnectionResult>) {
         let url = self.url.clone();
-        let realm = self.realm.clone();
+        let connection = self.clone();
         thread::spawn(move || {
             trace!("Beginning Connection");
             let connect_result = connect(url, |out| {
                 trace!("Got sender");
                 // Set up timeout
                 out.timeout(5000, CONNECTION_TIMEOUT).unwrap();
-                let info = Arc::new(Mutex::new(ConnectionInfo {
-                    protocol: String::new(),
-                    subscription_requests: HashMap::new(),
-                    unsubscription_requests: HashMap::new(),
-                    subscriptions: HashMap::new(),
-                    registrations: HashMap::new(),
-                    call_requests: HashMap::new(),
-                    registration_requests: HashMap::new(),
-                    unregistration_requests: HashMap::new(),
-                    sender: out,
-                    connection_state: ConnectionState::Connecting,
-                    publish_requests: HashMap::new(),
-                    shutdown_complete: None,
-                    session_id: 0,
-                }));
-
-                ConnectionHandler {
-                    state_transmission: tx.clone(),
-                    connection_info: info,
-                    realm: realm.clone(),
-                }
+                connection.handler(Box::new(out), &JsonSerializer, tx.clone())
             }).map_err(|e| Error::new(ErrorKind::WSError(e)));
             debug!("Result of connection: {:?}", connect_result);
             match connect_result {
@@ -198,11 +440,109 @@ impl Connection {
                 }
             }
         });
-        let info = try!(rx.recv().unwrap());
-        Ok(Client {
+    }
+
+    fn connect_rawsocket(&self, url: &Url, tx: CHSender<ConnectionResult>) -> WampResult<()> {
+        let address = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidState(
+                    "RawSocket URLs need a host and a port",
+                )))
+            }
+        };
+        self.run_rawsocket(move || TcpStream::connect(&address[..]), tx);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(&self, url: &Url, tx: CHSender<ConnectionResult>) -> WampResult<()> {
+        if url.path().is_empty() {
+            return Err(Error::new(ErrorKind::InvalidState(
+                "Unix socket URLs need a path",
+            )));
+        }
+        let path = url.path().to_string();
+        self.run_rawsocket(move || UnixStream::connect(&path), tx);
+        Ok(())
+    }
+
+    /// Runs a RawSocket connection over the stream opened by `open` on a new thread.
+    fn run_rawsocket<S, F>(&self, open: F, tx: CHSender<ConnectionResult>)
+    where
+        S: Socket,
+        F: FnOnce() -> io::Result<S> + Send + 'static,
+    {
+        let connection = self.clone();
+        thread::spawn(move || {
+            trace!("Beginning RawSocket Connection");
+            let format = connection
+                .serializer
+                .and_then(|serializer| RawSocketFormat::from_protocol(serializer.protocol()))
+                .unwrap_or(RawSocketFormat::MsgPack);
+            let opened = open()
+                .map_err(WSError::from)
+                .and_then(|mut stream| {
+                    let max_length = try!(rawsocket::connect(&mut stream, format));
+                    rawsocket::open(stream, format, max_length)
+                });
+            let (sender, events) = match opened {
+                Ok(opened) => opened,
+                Err(e) => {
+                    tx.send(Err(Error::new(ErrorKind::WSError(e)))).unwrap();
+                    return;
+                }
+            };
+            sender.timeout(5000, CONNECTION_TIMEOUT).unwrap();
+            let serializer = format.message_serializer();
+            let mut handler = connection.handler(Box::new(sender.clone()), serializer, tx);
+            match handler.send_hello() {
+                Ok(()) => transport::run(&mut handler, events, &sender),
+                Err(e) => {
+                    handler
+                        .state_transmission
+                        .send(Err(Error::new(ErrorKind::WSError(e))))
+                        .unwrap();
+                }
+            }
+        });
+    }
+
+    fn handler(
+        &self,
+        sender: Box<Transport>,
+        serializer: &'static Serializer,
+        tx: CHSender<ConnectionResult>,
+    ) -> ConnectionHandler {
+        let info = Arc::new(Mutex::new(ConnectionInfo {
+            serializer: serializer,
+            subscription_requests: HashMap::new(),
+            unsubscription_requests: HashMap::new(),
+            subscriptions: HashMap::new(),
+            registrations: HashMap::new(),
+            call_requests: HashMap::new(),
+            call_progress: HashMap::new(),
+            invocations: HashMap::new(),
+            registration_requests: HashMap::new(),
+            unregistration_requests: HashMap::new(),
+            sender: sender,
+            connection_state: ConnectionState::Connecting,
+            publish_requests: HashMap::new(),
+            shutdown_complete: None,
+            session_id: 0,
+            realm: self.realm.clone(),
+            welcome_details: WelcomeDetails::default(),
+        }));
+
+        ConnectionHandler {
+            state_transmission: tx,
             connection_info: info,
-            max_session_id: 0,
-        })
+            realm: self.realm.clone(),
+            authid: self.authid.clone(),
+            credentials: self.credentials.clone(),
+            challenge: None,
+            serializer: self.serializer,
+        }
     }
 }
 
@@ -227,58 +567,37 @@ macro_rules! cancel_future {
 impl Handler for ConnectionHandler {
     fn on_open(&mut self, handshake: Handshake) -> WSResult<()> {
         debug!("Connection Opened");
-        let mut info = self.connection_info.lock().unwrap();
-        info.protocol = match try!(handshake.response.protocol()) {
-            Some(protocol) => protocol.to_string(),
-            None => {
-                warn!("Router did not specify protocol. Defaulting to wamp.2.json");
-                WAMP_JSON.to_string()
-            }
-        };
-
-        let hello_message =
-            Message::Hello(self.realm.clone(), HelloDetails::new(ClientRoles::new()));
-        debug!("Sending Hello message");
-        thread::sleep(Duration::from_millis(200));
-        match info.send_message(hello_message) {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                if let ErrorKind::WSError(e) = e.kind {
-                    Err(e)
-                } else {
-                    Err(WSError::new(WSErrorKind::Internal, "Unknown error"))
+        self.connection_info.lock().unwrap().serializer =
+            match try!(handshake.response.protocol()) {
+                Some(protocol) => match serializer::from_protocol(protocol) {
+                    Some(serializer) => serializer,
+                    None => {
+                        return Err(WSError::new(
+                            WSErrorKind::Protocol,
+                            "Router selected a protocol we did not ask for",
+                        ))
+                    }
+                },
+                None => {
+                    warn!("Router did not specify protocol. Defaulting to wamp.2.json");
+                    &JsonSerializer
                 }
-            }
-        }
+            };
+        self.send_hello()
     }
 
     fn on_message(&mut self, message: WSMessage) -> WSResult<()> {
         debug!("Server sent a message: {:?}", message);
-        match message {
-            WSMessage::Text(message) => match serde_json::from_str(&message) {
-                Ok(message) => {
-                    if !self.handle_message(message) {
-                        return self.connection_info.lock().unwrap().sender.shutdown();
-                    }
-                }
-                Err(_) => {
-                    error!("Received unknown message: {}", message);
-                    return Ok(());
-                }
-            },
-            WSMessage::Binary(message) => {
-                let mut de = RMPDeserializer::new(Cursor::new(&*message));
-                match Deserialize::deserialize(&mut de) {
-                    Ok(message) => {
-                        if !self.handle_message(message) {
-                            return self.connection_info.lock().unwrap().sender.shutdown();
-                        }
-                    }
-                    Err(_) => {
-                        error!("Could not understand MsgPack message");
-                    }
+        let serializer = self.connection_info.lock().unwrap().serializer;
+        match serializer.deserialize(message) {
+            Ok(message) => {
+                if !self.handle_message(message) {
+                    return self.connection_info.lock().unwrap().sender.shutdown();
                 }
             }
+            Err(e) => {
+                error!("Could not understand message: {}", e);
+            }
         }
         Ok(())
     }
@@ -294,6 +613,10 @@ impl Handler for ConnectionHandler {
         cancel_future_tuple!(info.unregistration_requests);
         cancel_future!(info.publish_requests);
         cancel_future!(info.call_requests);
+        info.call_progress.clear();
+        for (_, interrupted) in info.invocations.drain() {
+            interrupted.store(true, Ordering::SeqCst);
+        }
         info.sender.shutdown().ok();
 
         if let Some(promise) = info.shutdown_complete.take() {
@@ -318,13 +641,62 @@ impl Handler for ConnectionHandler {
     fn build_request(&mut self, url: &Url) -> WSResult<Request> {
         trace!("Building request");
         let mut request = try!(Request::from_url(url));
-        request.add_protocol(WAMP_MSGPACK);
-        request.add_protocol(WAMP_JSON);
+        match self.serializer {
+            Some(serializer) => request.add_protocol(serializer.protocol()),
+            None => {
+                for serializer in serializer::all() {
+                    request.add_protocol(serializer.protocol());
+                }
+            }
+        }
         Ok(request)
     }
 }
 
 impl ConnectionHandler {
+    /// Introduces the client to the router once the transport is open.
+    fn send_hello(&mut self) -> WSResult<()> {
+        let mut details = HelloDetails::new(ClientRoles::new());
+        details.authid = self.authid.clone();
+        details.authmethods = self.credentials
+            .iter()
+            .map(|credential| credential.authmethod().to_string())
+            .collect();
+        let mut authextra = HashMap::new();
+        for credential in &mut self.credentials {
+            match *credential {
+                Credential::Cryptosign { ref public_key, .. } => {
+                    authextra.insert("pubkey".to_string(), Value::String(public_key.clone()));
+                }
+                Credential::Scram {
+                    ref mut client_nonce,
+                    ..
+                } => {
+                    let nonce = auth::nonce();
+                    authextra.insert("nonce".to_string(), Value::String(nonce.clone()));
+                    *client_nonce = Some(nonce);
+                }
+                _ => {}
+            }
+        }
+        if !authextra.is_empty() {
+            details.authextra = Some(authextra);
+        }
+        let hello_message = Message::Hello(self.realm.clone(), details);
+        debug!("Sending Hello message");
+        thread::sleep(Duration::from_millis(200));
+        match self.connection_info.lock().unwrap().send_message(hello_message) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                if let ErrorKind::WSError(e) = e.kind {
+                    Err(e)
+                } else {
+                    Err(WSError::new(WSErrorKind::Internal, "Unknown error"))
+                }
+            }
+        }
+    }
+
     fn handle_message(&mut self, message: Message) -> bool {
         let mut info = self.connection_info.lock().unwrap();
         debug!(
@@ -334,7 +706,11 @@ impl ConnectionHandler {
         match info.connection_state {
             ConnectionState::Connecting => match message {
                 Message::Welcome(session_id, details) => {
-                    self.handle_welcome(info, session_id, details)
+                    return self.handle_welcome(info, session_id, details)
+                }
+                Message::Challenge(authmethod, extra) => {
+                    self.challenge = Some(extra.clone());
+                    return self.handle_challenge(info, authmethod, extra);
                 }
                 Message::Abort(_, reason) => {
                     self.handle_abort(info, reason);
@@ -349,8 +725,8 @@ impl ConnectionHandler {
                         self.handle_subscribed(info, request_id, subscription_id)
                     }
                     Message::Unsubscribed(request_id) => self.handle_unsubscribed(info, request_id),
-                    Message::Event(subscription_id, _, _, args, kwargs) => {
-                        self.handle_event(info, subscription_id, args, kwargs)
+                    Message::Event(subscription_id, _, details, args, kwargs) => {
+                        self.handle_event(info, subscription_id, details, args, kwargs)
                     }
                     Message::Published(request_id, publication_id) => {
                         self.handle_published(info, request_id, publication_id)
@@ -369,6 +745,9 @@ impl ConnectionHandler {
                             kwargs,
                         )
                     }
+                    Message::Interrupt(request_id, options) => {
+                        self.handle_interrupt(info, request_id, options)
+                    }
                     Message::Result(call_id, details, args, kwargs) => {
                         self.handle_result(info, call_id, details, args, kwargs)
                     }
@@ -503,7 +882,8 @@ impl ConnectionHandler {
         info!("Received a registered notification");
         match info.registration_requests.remove(&request_id) {
             Some((promise, callback, procedure)) => {
-                info.registrations.insert(registration_id, callback);
+                info.registrations
+                    .insert(registration_id, Arc::new(Mutex::new(callback)));
                 drop(info);
                 let registration = Registration {
                     procedure: procedure,
@@ -612,25 +992,73 @@ impl ConnectionHandler {
         &self,
         mut info: MutexGuard<ConnectionInfo>,
         session_id: ID,
-        _details: WelcomeDetails,
-    ) {
+        details: WelcomeDetails,
+    ) -> bool {
+        let verified = self.credentials
+            .iter()
+            .filter(|credential| {
+                details.authmethod.as_ref().map(|authmethod| &authmethod[..])
+                    == Some(credential.authmethod())
+            })
+            .all(|credential| credential.verify_welcome(self.challenge.as_ref(), &details));
+        if !verified {
+            error!("Router could not prove that it knows our credentials");
+            info.connection_state = ConnectionState::ShuttingDown;
+            drop(info);
+            self.state_transmission
+                .send(Err(Error::new(ErrorKind::HandshakeError(
+                    Reason::AuthenticationFailed,
+                ))))
+                .ok();
+            return false;
+        }
         info.session_id = session_id;
+        info.welcome_details = details;
         info.connection_state = ConnectionState::Connected;
         drop(info);
         self.state_transmission
             .send(Ok(Arc::clone(&self.connection_info)))
             .unwrap();
+        true
+    }
+
+    fn handle_challenge(
+        &self,
+        mut info: MutexGuard<ConnectionInfo>,
+        authmethod: String,
+        extra: Dict,
+    ) -> bool {
+        debug!("Router challenged us with {}", authmethod);
+        let signature = self.credentials
+            .iter()
+            .find(|credential| credential.authmethod() == authmethod)
+            .and_then(|credential| credential.sign(&extra));
+        match signature {
+            Some(signature) => info
+                .send_message(Message::Authenticate(signature, HashMap::new()))
+                .is_ok(),
+            None => {
+                error!("Unable to answer {} challenge from router", authmethod);
+                info.connection_state = ConnectionState::ShuttingDown;
+                false
+            }
+        }
     }
 
     fn handle_abort(&self, mut info: MutexGuard<ConnectionInfo>, reason: Reason) {
         error!("Router aborted connection.  Reason: {:?}", reason);
         info.connection_state = ConnectionState::ShuttingDown;
+        drop(info);
+        self.state_transmission
+            .send(Err(Error::new(ErrorKind::HandshakeError(reason))))
+            .ok();
     }
 
     fn handle_event(
         &self,
         mut info: MutexGuard<ConnectionInfo>,
         subscription_id: ID,
+        details: EventDetails,
         args: Option<List>,
         kwargs: Option<Dict>,
     ) {
@@ -639,7 +1067,7 @@ impl ConnectionHandler {
         match info.subscriptions.get_mut(&subscription_id) {
             Some(subscription) => {
                 let callback = &mut subscription.callback;
-                callback(args, kwargs);
+                callback(args, kwargs, details);
             }
             None => {
                 warn!(
@@ -655,32 +1083,14 @@ impl ConnectionHandler {
         mut info: MutexGuard<ConnectionInfo>,
         request_id: ID,
         registration_id: ID,
-        _details: InvocationDetails,
+        details: InvocationDetails,
         args: Option<List>,
         kwargs: Option<Dict>,
     ) {
         let args = args.unwrap_or_default();
         let kwargs = kwargs.unwrap_or_default();
-        let message = match info.registrations.get_mut(&registration_id) {
-            Some(registration) => {
-                let callback = &mut registration.callback;
-                match callback(args, kwargs) {
-                    Ok((rargs, rkwargs)) => {
-                        Message::Yield(request_id, YieldOptions::new(), rargs, rkwargs)
-                    }
-                    Err(error) => {
-                        let (reason, args, kwargs) = error.into_tuple();
-                        Message::Error(
-                            ErrorType::Invocation,
-                            request_id,
-                            HashMap::new(),
-                            reason,
-                            args,
-                            kwargs,
-                        )
-                    }
-                }
-            }
+        let registration = match info.registrations.get(&registration_id) {
+            Some(registration) => Arc::clone(registration),
             None => {
                 warn!(
                     "Received an invocation for a procedure we don't have.  ID: {}",
@@ -689,19 +1099,109 @@ impl ConnectionHandler {
                 return;
             }
         };
-        info.send_message(message).ok();
+        let mut registration = registration.lock().unwrap();
+        let interrupted = Arc::new(AtomicBool::new(false));
+        if let RegistrationCallback::Deferred(_) = registration.callback {
+            info.invocations.insert(request_id, Arc::clone(&interrupted));
+        }
+        // Release the connection while the callback runs, so that deferred invocations
+        // can respond before the callback returns.  The registration stays in place, so it can
+        // be unregistered meanwhile.
+        drop(info);
+        let message = match registration.callback {
+            RegistrationCallback::Immediate(ref mut callback) => {
+                Some(invocation_response(request_id, callback(args, kwargs)))
+            }
+            RegistrationCallback::Deferred(ref mut callback) => {
+                let invocation = Invocation {
+                    connection_info: Arc::clone(&self.connection_info),
+                    request_id: request_id,
+                    receive_progress: details.receive_progress,
+                    interrupted: interrupted,
+                    caller: details.caller,
+                    caller_authid: details.caller_authid,
+                    caller_authrole: details.caller_authrole,
+                    ppt_keyid: details.ppt_keyid,
+                };
+                callback(args, kwargs, invocation);
+                None
+            }
+        };
+        if let Some(message) = message {
+            self.connection_info
+                .lock()
+                .unwrap()
+                .send_message(message)
+                .ok();
+        }
+    }
+
+    fn handle_interrupt(
+        &self,
+        mut info: MutexGuard<ConnectionInfo>,
+        request_id: ID,
+        options: InterruptOptions,
+    ) {
+        debug!(
+            "Received an interrupt (id: {}, mode: {:?})",
+            request_id, options.mode
+        );
+        let interrupted = match options.mode {
+            // The router has already answered the caller, so the invocation is answered for the
+            // callee and its own result will be discarded
+            CancelMode::KillNoWait => info.invocations.remove(&request_id),
+            // The caller waits for the callee's answer, which is left to the invocation
+            CancelMode::Kill | CancelMode::Skip => info.invocations.get(&request_id).cloned(),
+        };
+        match interrupted {
+            Some(interrupted) => {
+                interrupted.store(true, Ordering::SeqCst);
+                if options.mode == CancelMode::KillNoWait {
+                    info.send_message(Message::Error(
+                        ErrorType::Invocation,
+                        request_id,
+                        HashMap::new(),
+                        Reason::Cancelled,
+                        None,
+                        None,
+                    )).ok();
+                }
+            }
+            None => {
+                debug!(
+                    "Received an interrupt for an invocation that has already completed.  ID: {}",
+                    request_id
+                );
+            }
+        }
     }
 
     fn handle_result(
         &self,
         mut info: MutexGuard<ConnectionInfo>,
         call_id: ID,
-        _details: ResultDetails,
+        details: ResultDetails,
         args: Option<List>,
         kwargs: Option<Dict>,
     ) {
         let args = args.unwrap_or_default();
         let kwargs = kwargs.unwrap_or_default();
+        if details.progress {
+            match info.call_progress.get_mut(&call_id) {
+                Some(progress) => {
+                    let callback = &mut progress.callback;
+                    callback(args, kwargs);
+                }
+                None => {
+                    warn!(
+                        "Received a progressive result for a call that didn't request one.  ID: {}",
+                        call_id
+                    );
+                }
+            }
+            return;
+        }
+        info.call_progress.remove(&call_id);
         match info.call_requests.remove(&call_id) {
             Some(promise) => {
                 promise.complete((args, kwargs));
@@ -723,6 +1223,7 @@ impl ConnectionHandler {
         args: Option<List>,
         kwargs: Option<Dict>,
     ) {
+        info.call_progress.remove(&request_id);
         match info.call_requests.remove(&request_id) {
             Some(promise) => promise.fail(CallError::new(reason, args, kwargs)),
             None => {
@@ -785,11 +1286,64 @@ impl Client {
         self.max_session_id
     }
 
+    /// The id the router assigned to this session.
+    pub fn session_id(&self) -> ID {
+        self.connection_info.lock().unwrap().session_id
+    }
+
+    /// The realm this session joined.
+    pub fn realm(&self) -> URI {
+        self.connection_info.lock().unwrap().realm.clone()
+    }
+
+    /// The authid the router authenticated this session as, if any.
+    pub fn authid(&self) -> Option<String> {
+        self.connection_info.lock().unwrap().welcome_details.authid.clone()
+    }
+
+    /// The role the router gave this session, if it announced one.
+    pub fn authrole(&self) -> Option<String> {
+        self.connection_info.lock().unwrap().welcome_details.authrole.clone()
+    }
+
+    /// The method the router used to authenticate this session, if it announced one.
+    pub fn authmethod(&self) -> Option<String> {
+        self.connection_info.lock().unwrap().welcome_details.authmethod.clone()
+    }
+
+    /// The roles and features the router announced when this session joined.
+    pub fn router_roles(&self) -> RouterRoles {
+        self.connection_info
+            .lock()
+            .unwrap()
+            .welcome_details
+            .roles()
+            .clone()
+    }
+
     pub fn subscribe_with_pattern(
         &mut self,
         topic_pattern: URI,
         callback: Box<FnMut(List, Dict)>,
         policy: MatchingPolicy,
+    ) -> WampResult<Future<Subscription, CallError>> {
+        let mut callback = callback;
+        self.subscribe_with_details(
+            topic_pattern,
+            Box::new(move |args: List, kwargs: Dict, _details: EventDetails| {
+                callback(args, kwargs)
+            }),
+            policy,
+        )
+    }
+
+    /// Subscribes to a topic pattern with a callback that also receives the details of each
+    /// event, such as the identity of the publisher.
+    pub fn subscribe_with_details(
+        &mut self,
+        topic_pattern: URI,
+        callback: Box<FnMut(List, Dict, EventDetails)>,
+        policy: MatchingPolicy,
     ) -> WampResult<Future<Subscription, CallError>> {
         // Send a subscribe messages
         let request_id = self.get_next_session_id();
@@ -800,6 +1354,12 @@ impl Client {
             options.pattern_match = policy
         }
         let mut info = self.connection_info.lock().unwrap();
+        try!(require_feature(
+            policy == MatchingPolicy::Strict
+                || info.welcome_details.roles().broker_features().pattern_based_subscription,
+            ErrorType::Subscribe,
+            request_id,
+        ));
         info.subscription_requests
             .insert(request_id, (complete, callback, topic_pattern.clone()));
         try!(info.send_message(Message::Subscribe(request_id, options, topic_pattern)));
@@ -819,6 +1379,50 @@ impl Client {
         procedure_pattern: URI,
         callback: Callback,
         policy: MatchingPolicy,
+    ) -> WampResult<Future<Registration, CallError>> {
+        self.register_callback(
+            procedure_pattern,
+            RegistrationCallback::Immediate(callback),
+            policy,
+        )
+    }
+
+    pub fn register(
+        &mut self,
+        procedure: URI,
+        callback: Callback,
+    ) -> WampResult<Future<Registration, CallError>> {
+        self.register_with_pattern(procedure, callback, MatchingPolicy::Strict)
+    }
+
+    /// Registers a procedure whose results are delivered through an `Invocation` handle, which
+    /// allows the callee to send progressive results before the final one.
+    pub fn register_deferred_with_pattern(
+        &mut self,
+        procedure_pattern: URI,
+        callback: DeferredCallback,
+        policy: MatchingPolicy,
+    ) -> WampResult<Future<Registration, CallError>> {
+        self.register_callback(
+            procedure_pattern,
+            RegistrationCallback::Deferred(callback),
+            policy,
+        )
+    }
+
+    pub fn register_deferred(
+        &mut self,
+        procedure: URI,
+        callback: DeferredCallback,
+    ) -> WampResult<Future<Registration, CallError>> {
+        self.register_deferred_with_pattern(procedure, callback, MatchingPolicy::Strict)
+    }
+
+    fn register_callback(
+        &mut self,
+        procedure_pattern: URI,
+        callback: RegistrationCallback,
+        policy: MatchingPolicy,
     ) -> WampResult<Future<Registration, CallError>> {
         // Send a register message
         let request_id = self.get_next_session_id();
@@ -831,20 +1435,18 @@ impl Client {
         debug!("Acquiring lock on connection info");
         let mut info = self.connection_info.lock().unwrap();
         debug!("Lock on connection info acquired");
+        try!(require_feature(
+            policy == MatchingPolicy::Strict
+                || info.welcome_details.roles().dealer_features().pattern_based_registration,
+            ErrorType::Register,
+            request_id,
+        ));
         info.registration_requests
             .insert(request_id, (complete, callback, procedure_pattern.clone()));
         try!(info.send_message(Message::Register(request_id, options, procedure_pattern)));
         Ok(future)
     }
 
-    pub fn register(
-        &mut self,
-        procedure: URI,
-        callback: Callback,
-    ) -> WampResult<Future<Registration, CallError>> {
-        self.register_with_pattern(procedure, callback, MatchingPolicy::Strict)
-    }
-
     pub fn unsubscribe(&mut self, subscription: Subscription) -> WampResult<Future<(), CallError>> {
         let request_id = self.get_next_session_id();
         let mut info = self.connection_info.lock().unwrap();
@@ -892,27 +1494,223 @@ impl Client {
             ))
     }
 
+    /// Publishes an event with the given options.  If the options ask for an acknowledgement,
+    /// the returned future completes with the publication id.
+    pub fn publish_with_options(
+        &mut self,
+        topic: URI,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+        options: PublishOptions,
+    ) -> WampResult<Option<Future<ID, CallError>>> {
+        info!("Publishing to {:?} with {:?} | {:?}", topic, args, kwargs);
+        let request_id = self.get_next_session_id();
+        let mut info = self.connection_info.lock().unwrap();
+        {
+            let features = info.welcome_details.roles().broker_features();
+            let listing = options.exclude.is_some() || options.exclude_authid.is_some()
+                || options.exclude_authrole.is_some()
+                || options.eligible.is_some()
+                || options.eligible_authid.is_some()
+                || options.eligible_authrole.is_some();
+            try!(require_feature(
+                !options.disclose_me || features.publisher_identification,
+                ErrorType::Publish,
+                request_id,
+            ));
+            try!(require_feature(
+                !listing || features.subscriber_blackwhite_listing,
+                ErrorType::Publish,
+                request_id,
+            ));
+            try!(require_feature(
+                options.exclude_me.is_none() || features.publisher_exclusion,
+                ErrorType::Publish,
+                request_id,
+            ));
+            try!(require_feature(
+                !options.retain || features.event_retention,
+                ErrorType::Publish,
+                request_id,
+            ));
+            try!(require_feature(
+                options.ppt_scheme.is_none() || features.payload_passthru_mode,
+                ErrorType::Publish,
+                request_id,
+            ));
+        }
+        let future = if options.should_acknowledge() {
+            let (complete, future) = Future::<ID, CallError>::pair();
+            info.publish_requests.insert(request_id, complete);
+            Some(future)
+        } else {
+            None
+        };
+        try!(info.send_message(Message::Publish(
+            request_id,
+            options,
+            topic,
+            args,
+            kwargs
+        )));
+        Ok(future)
+    }
+
+    /// Publishes an event with arguments encrypted with the given key, so that only subscribers
+    /// holding the same key can read them.
+    pub fn publish_encrypted(
+        &mut self,
+        topic: URI,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+        key: &PayloadKey,
+    ) -> WampResult<()> {
+        let payload = try!(key.encrypt(args, kwargs));
+        let mut options = PublishOptions::new(false);
+        options.ppt_scheme = Some(PPT_SCHEME.to_string());
+        options.ppt_serializer = Some(PPT_SERIALIZER.to_string());
+        options.ppt_cipher = Some(PPT_CIPHER.to_string());
+        options.ppt_keyid = Some(key.keyid().to_string());
+        try!(self.publish_with_options(topic, Some(payload), None, options));
+        Ok(())
+    }
+
     pub fn call(
         &mut self,
         procedure: URI,
         args: Option<List>,
         kwargs: Option<Dict>,
     ) -> WampResult<Future<(List, Dict), CallError>> {
+        let (_, future) = try!(self.call_with_options(
+            procedure,
+            args,
+            kwargs,
+            CallOptions::new(),
+            None
+        ));
+        Ok(future)
+    }
+
+    /// Calls a procedure, asking the callee for progressive results.  Each progressive result is
+    /// passed to `progress` before the returned future completes with the final result.
+    pub fn call_with_progress(
+        &mut self,
+        procedure: URI,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+        progress: Box<FnMut(List, Dict)>,
+    ) -> WampResult<Future<(List, Dict), CallError>> {
+        let (_, future) = try!(self.call_with_options(
+            procedure,
+            args,
+            kwargs,
+            CallOptions::new(),
+            Some(progress)
+        ));
+        Ok(future)
+    }
+
+    /// Calls a procedure with the given options.
+    ///
+    /// Progressive results are requested if, and only if, a `progress` callback is given.
+    /// Returns the id of the call, which can be passed to `cancel()`, along with the future
+    /// result.
+    pub fn call_with_options(
+        &mut self,
+        procedure: URI,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+        mut options: CallOptions,
+        progress: Option<Box<FnMut(List, Dict)>>,
+    ) -> WampResult<(ID, Future<(List, Dict), CallError>)> {
         info!("Calling {:?} with {:?} | {:?}", procedure, args, kwargs);
         let request_id = self.get_next_session_id();
         let (complete, future) = Future::<(List, Dict), CallError>::pair();
+        options.receive_progress = progress.is_some();
         let mut info = self.connection_info.lock().unwrap();
+        {
+            let features = info.welcome_details.roles().dealer_features();
+            try!(require_feature(
+                !options.receive_progress || features.progressive_call_results,
+                ErrorType::Call,
+                request_id,
+            ));
+            try!(require_feature(
+                options.timeout == 0 || features.call_timeout,
+                ErrorType::Call,
+                request_id,
+            ));
+            try!(require_feature(
+                !options.disclose_me || features.caller_identification,
+                ErrorType::Call,
+                request_id,
+            ));
+            try!(require_feature(
+                options.ppt_scheme.is_none() || features.payload_passthru_mode,
+                ErrorType::Call,
+                request_id,
+            ));
+        }
         info.call_requests.insert(request_id, complete);
+        if let Some(progress) = progress {
+            info.call_progress
+                .insert(request_id, ProgressCallbackWrapper { callback: progress });
+        }
         try!(info.send_message(Message::Call(
             request_id,
-            CallOptions::new(),
+            options,
             procedure,
             args,
             kwargs
         )));
+        Ok((request_id, future))
+    }
+
+    /// Calls a procedure with arguments encrypted with the given key, so that only callees
+    /// holding the same key can read them.  The result is returned as the callee sent it, and can
+    /// be read with `PayloadKey::decrypt()` if the callee encrypted it.
+    pub fn call_encrypted(
+        &mut self,
+        procedure: URI,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+        key: &PayloadKey,
+    ) -> WampResult<Future<(List, Dict), CallError>> {
+        let payload = try!(key.encrypt(args, kwargs));
+        let mut options = CallOptions::new();
+        options.ppt_scheme = Some(PPT_SCHEME.to_string());
+        options.ppt_serializer = Some(PPT_SERIALIZER.to_string());
+        options.ppt_cipher = Some(PPT_CIPHER.to_string());
+        options.ppt_keyid = Some(key.keyid().to_string());
+        let (_, future) = try!(self.call_with_options(
+            procedure,
+            Some(payload),
+            None,
+            options,
+            None
+        ));
         Ok(future)
     }
 
+    /// Cancels a call made with `call_with_options()`.
+    ///
+    /// The future for the call fails with `Reason::Cancelled` once the router has processed the
+    /// cancellation.  Cancelling a call that has already completed has no effect.
+    pub fn cancel(&mut self, call_id: ID, mode: CancelMode) -> WampResult<()> {
+        info!("Cancelling call {} with mode {:?}", call_id, mode);
+        let info = self.connection_info.lock().unwrap();
+        if !info.call_requests.contains_key(&call_id) {
+            debug!("Not cancelling call {}, since it is not active", call_id);
+            return Ok(());
+        }
+        try!(require_feature(
+            info.welcome_details.roles().dealer_features().call_canceling,
+            ErrorType::Call,
+            call_id,
+        ));
+        info.send_message(Message::Cancel(call_id, CancelOptions::new(mode)))
+    }
+
     pub fn publish_and_acknowledge(
         &mut self,
         topic: URI,
@@ -954,6 +1752,107 @@ impl Client {
     }
 }
 
+impl Invocation {
+    /// Whether the caller asked for progressive results.  If it did not, calls to `progress()`
+    /// are ignored.
+    #[inline]
+    pub fn receives_progress(&self) -> bool {
+        self.receive_progress
+    }
+
+    /// The session id of the caller, if the caller chose to disclose it.
+    #[inline]
+    pub fn caller(&self) -> Option<ID> {
+        self.caller
+    }
+
+    /// The authentication id of the caller, if the caller chose to disclose it.
+    #[inline]
+    pub fn caller_authid(&self) -> Option<&str> {
+        self.caller_authid.as_ref().map(|authid| &authid[..])
+    }
+
+    /// The authentication role of the caller, if the caller chose to disclose it.
+    #[inline]
+    pub fn caller_authrole(&self) -> Option<&str> {
+        self.caller_authrole.as_ref().map(|authrole| &authrole[..])
+    }
+
+    /// The id of the key the caller encrypted the arguments with, if they are encrypted.
+    #[inline]
+    pub fn payload_keyid(&self) -> Option<&str> {
+        self.ppt_keyid.as_ref().map(|keyid| &keyid[..])
+    }
+
+    /// Whether the caller has cancelled this invocation, or the connection has been lost.
+    /// Long running procedures should check this periodically and stop working once it is set.
+    #[inline]
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    /// Sends a progressive result to the caller, leaving the invocation open.
+    pub fn progress(&self, args: Option<List>, kwargs: Option<Dict>) -> WampResult<()> {
+        if !self.receive_progress {
+            debug!(
+                "Ignoring progressive result for an invocation that didn't request one.  ID: {}",
+                self.request_id
+            );
+            return Ok(());
+        }
+        let info = self.connection_info.lock().unwrap();
+        if self.is_interrupted() {
+            return Ok(());
+        }
+        let mut options = YieldOptions::new();
+        options.progress = true;
+        info.send_message(Message::Yield(self.request_id, options, args, kwargs))
+    }
+
+    /// Sends the final result (or error) of the invocation to the caller.  If the invocation has
+    /// already been answered, because it was killed without waiting or the connection was lost,
+    /// the result is discarded.
+    pub fn complete(self, result: CallResult<(Option<List>, Option<Dict>)>) -> WampResult<()> {
+        let mut info = self.connection_info.lock().unwrap();
+        if info.invocations.remove(&self.request_id).is_none() {
+            debug!(
+                "Discarding result for interrupted invocation.  ID: {}",
+                self.request_id
+            );
+            return Ok(());
+        }
+        info.send_message(invocation_response(self.request_id, result))
+    }
+
+    /// Sends the final result of the invocation encrypted with the given key, so that only the
+    /// caller can read it.  Errors are sent unencrypted.
+    pub fn complete_encrypted(
+        self,
+        key: &PayloadKey,
+        result: CallResult<(Option<List>, Option<Dict>)>,
+    ) -> WampResult<()> {
+        let (args, kwargs) = match result {
+            Ok(result) => result,
+            Err(error) => return self.complete(Err(error)),
+        };
+        let payload = try!(key.encrypt(args, kwargs));
+        let mut info = self.connection_info.lock().unwrap();
+        if info.invocations.remove(&self.request_id).is_none() {
+            debug!(
+                "Discarding result for interrupted invocation.  ID: {}",
+                self.request_id
+            );
+            return Ok(());
+        }
+        let mut options = YieldOptions::new();
+        options.ppt_scheme = Some(PPT_SCHEME.to_string());
+        options.ppt_serializer = Some(PPT_SERIALIZER.to_string());
+        options.ppt_cipher = Some(PPT_CIPHER.to_string());
+        options.ppt_keyid = Some(key.keyid().to_string());
+        info.send_message(Message::Yield(self.request_id, options, Some(payload), None))
+    }
+}
+
 impl fmt::Debug for ConnectionHandler {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -963,3 +1862,43 @@ impl fmt::Debug for ConnectionHandler {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Connection, ConnectionState};
+    use messages::{Message, Reason, Value, WelcomeDetails};
+    use serializer::JsonSerializer;
+    use std::collections::HashMap;
+    use std::sync::mpsc::channel;
+    use transport::testing::Recorder;
+    use ErrorKind;
+
+    #[test]
+    fn refusing_a_scram_welcome_without_a_challenge() {
+        let mut connection = Connection::new("ws://127.0.0.1:8090/ws", "realm1");
+        connection.set_scram_credentials("joe", "secret");
+        let (tx, rx) = channel();
+        let mut handler = connection.handler(Box::new(Recorder::new()), &JsonSerializer, tx);
+
+        let mut authextra = HashMap::new();
+        authextra.insert(
+            "scram_server_signature".to_string(),
+            Value::String("c2lnbmF0dXJl".to_string()),
+        );
+        let mut details = WelcomeDetails::default();
+        details.authid = Some("joe".to_string());
+        details.authmethod = Some("wamp-scram".to_string());
+        details.authextra = Some(authextra);
+        assert!(!handler.handle_message(Message::Welcome(1, details)));
+
+        match rx.try_recv() {
+            Ok(Err(e)) => match e.get_kind() {
+                ErrorKind::HandshakeError(Reason::AuthenticationFailed) => {}
+                _ => panic!("Expected the handshake to fail authentication"),
+            },
+            _ => panic!("Expected the handshake to fail"),
+        }
+        let info = handler.connection_info.lock().unwrap();
+        assert_eq!(info.connection_state, ConnectionState::ShuttingDown);
+    }
+}
@@ -1,6 +1,7 @@
-use super::{ErrorType, Message, ID};
+use super::{ErrorType, Message, WampResult, ID};
 use messages::{self, Reason};
 use rmp_serde::decode::Error as MsgPackError;
+use serde_cbor::Error as CBORError;
 use serde_json::Error as JSONError;
 use std::fmt;
 use std::sync::mpsc::SendError;
@@ -23,12 +24,26 @@ pub enum ErrorKind {
     Closing(String),
     JSONError(JSONError),
     MsgPackError(MsgPackError),
+    CBORError(CBORError),
     MalformedData,
     InvalidMessageType(Message),
     InvalidState(&'static str),
     Timeout,
     ErrorReason(ErrorType, ID, Reason),
 }
+/// Refuses a request that relies on a feature the other peer did not announce.
+pub fn require_feature(announced: bool, error_type: ErrorType, request_id: ID) -> WampResult<()> {
+    if announced {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::ErrorReason(
+            error_type,
+            request_id,
+            Reason::OptionNotAllowed,
+        )))
+    }
+}
+
 impl Error {
     pub fn new(kind: ErrorKind) -> Error {
         Error { kind: kind }
@@ -59,6 +74,7 @@ impl ErrorKind {
             ErrorKind::ThreadError(ref e) => e.to_string(),
             ErrorKind::JSONError(ref e) => e.to_string(),
             ErrorKind::MsgPackError(ref e) => e.to_string(),
+            ErrorKind::CBORError(ref e) => e.to_string(),
             ErrorKind::ErrorReason(_, _, ref s) => s.to_string(),
             ErrorKind::Closing(ref s) => s.clone(),
             ErrorKind::UnexpectedMessage(s) | ErrorKind::InvalidState(s) => s.to_string(),
@@ -1,9 +1,15 @@
 #![cfg_attr(feature = "cargo-clippy", allow(match_same_arms))]
+extern crate base64;
+extern crate hex;
+extern crate ring;
+extern crate untrusted;
 extern crate serde;
+extern crate serde_cbor;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 extern crate eventual;
+extern crate httparse;
 extern crate itertools;
 extern crate rand;
 extern crate rmp;
@@ -14,19 +20,25 @@ extern crate ws;
 #[macro_use]
 extern crate log;
 
+mod auth;
 pub mod client;
 mod error;
 mod messages;
+mod payload;
 pub mod router;
+mod serializer;
+mod transport;
 mod utils;
 
 use self::error::*;
 
 pub use client::{Client, Connection};
-pub use messages::{ArgDict, ArgList, CallError, Dict, InvocationPolicy, List, MatchingPolicy,
-                   Reason, Value, URI};
+pub use messages::{ArgDict, ArgList, CallError, CallOptions, CancelMode, Dict, EventDetails,
+                   InvocationPolicy, List, MatchingPolicy, PublishOptions, Reason, RouterRoles,
+                   Value, URI};
 use messages::{ErrorType, Message};
-pub use router::Router;
+pub use payload::PayloadKey;
+pub use router::{Action, Permissions, Router, TicketValidator};
 
 pub type CallResult<T> = Result<T, CallError>;
 pub type WampResult<T> = Result<T, Error>;
@@ -19,6 +19,8 @@ pub enum Message {
     Hello(URI, HelloDetails),
     Welcome(ID, WelcomeDetails),
     Abort(ErrorDetails, Reason),
+    Challenge(String, Dict),
+    Authenticate(String, Dict),
     Goodbye(ErrorDetails, Reason),
     Error(ErrorType, ID, Dict, Reason, Option<List>, Option<Dict>),
     Subscribe(ID, SubscribeOptions, URI),
@@ -33,7 +35,9 @@ pub enum Message {
     Unregister(ID, ID),
     Unregistered(ID),
     Call(ID, CallOptions, URI, Option<List>, Option<Dict>),
+    Cancel(ID, CancelOptions),
     Invocation(ID, ID, InvocationDetails, Option<List>, Option<Dict>),
+    Interrupt(ID, InterruptOptions),
     Yield(ID, YieldOptions, Option<List>, Option<Dict>),
     Result(ID, ResultDetails, Option<List>, Option<Dict>),
 }
@@ -68,6 +72,12 @@ impl serde::Serialize for Message {
                 (2, session, details).serialize(serializer)
             }
             Message::Abort(ref details, ref reason) => (3, details, reason).serialize(serializer),
+            Message::Challenge(ref authmethod, ref extra) => {
+                (4, authmethod, extra).serialize(serializer)
+            }
+            Message::Authenticate(ref signature, ref extra) => {
+                (5, signature, extra).serialize(serializer)
+            }
             Message::Goodbye(ref details, ref reason) => (6, details, reason).serialize(serializer),
             Message::Error(ref ty, id, ref details, ref reason, ref args, ref kwargs) => {
                 serialize_with_args!(args, kwargs, serializer, 8, ty, id, details, reason)
@@ -112,9 +122,11 @@ impl serde::Serialize for Message {
             Message::Call(id, ref options, ref topic, ref args, ref kwargs) => {
                 serialize_with_args!(args, kwargs, serializer, 48, id, options, topic)
             }
+            Message::Cancel(id, ref options) => (49, id, options).serialize(serializer),
             Message::Invocation(id, registration_id, ref details, ref args, ref kwargs) => {
                 serialize_with_args!(args, kwargs, serializer, 68, id, registration_id, details)
             }
+            Message::Interrupt(id, ref options) => (69, id, options).serialize(serializer),
             Message::Yield(id, ref options, ref args, ref kwargs) => {
                 serialize_with_args!(args, kwargs, serializer, 70, id, options)
             }
@@ -182,6 +194,36 @@ impl MessageVisitor {
         Ok(Message::Abort(details, reason))
     }
 
+    fn visit_challenge<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
+    where
+        V: serde::de::SeqAccess<'de>,
+    {
+        let authmethod = try_or!(
+            visitor.next_element(),
+            "Challenge message ended before auth method"
+        );
+        let extra = try_or!(
+            visitor.next_element(),
+            "Challenge message ended before extra dict"
+        );
+        Ok(Message::Challenge(authmethod, extra))
+    }
+
+    fn visit_authenticate<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
+    where
+        V: serde::de::SeqAccess<'de>,
+    {
+        let signature = try_or!(
+            visitor.next_element(),
+            "Authenticate message ended before signature"
+        );
+        let extra = try_or!(
+            visitor.next_element(),
+            "Authenticate message ended before extra dict"
+        );
+        Ok(Message::Authenticate(signature, extra))
+    }
+
     fn visit_goodbye<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
     where
         V: serde::de::SeqAccess<'de>,
@@ -433,6 +475,21 @@ impl MessageVisitor {
         Ok(Message::Call(id, options, topic, args, kwargs))
     }
 
+    fn visit_cancel<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
+    where
+        V: serde::de::SeqAccess<'de>,
+    {
+        let id = try_or!(
+            visitor.next_element(),
+            "Cancel message ended before call id"
+        );
+        let options = try_or!(
+            visitor.next_element(),
+            "Cancel message ended before options dict"
+        );
+        Ok(Message::Cancel(id, options))
+    }
+
     fn visit_invocation<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
     where
         V: serde::de::SeqAccess<'de>,
@@ -460,6 +517,21 @@ impl MessageVisitor {
         ))
     }
 
+    fn visit_interrupt<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
+    where
+        V: serde::de::SeqAccess<'de>,
+    {
+        let id = try_or!(
+            visitor.next_element(),
+            "Interrupt message ended before invocation id"
+        );
+        let options = try_or!(
+            visitor.next_element(),
+            "Interrupt message ended before options dict"
+        );
+        Ok(Message::Interrupt(id, options))
+    }
+
     fn visit_yield<'de, V>(&self, mut visitor: V) -> Result<Message, V::Error>
     where
         V: serde::de::SeqAccess<'de>,
@@ -511,6 +583,8 @@ impl<'de> serde::de::Visitor<'de> for MessageVisitor {
             1 => self.visit_hello(visitor),
             2 => self.visit_welcome(visitor),
             3 => self.visit_abort(visitor),
+            4 => self.visit_challenge(visitor),
+            5 => self.visit_authenticate(visitor),
             6 => self.visit_goodbye(visitor),
             8 => self.visit_error(visitor),
             32 => self.visit_subscribe(visitor),
@@ -525,7 +599,9 @@ impl<'de> serde::de::Visitor<'de> for MessageVisitor {
             66 => self.visit_unregister(visitor),
             67 => self.visit_unregistered(visitor),
             48 => self.visit_call(visitor),
+            49 => self.visit_cancel(visitor),
             68 => self.visit_invocation(visitor),
+            69 => self.visit_interrupt(visitor),
             70 => self.visit_yield(visitor),
             50 => self.visit_result(visitor),
             _ => Err(serde::de::Error::custom("Unknown message type")),
@@ -535,14 +611,15 @@ impl<'de> serde::de::Visitor<'de> for MessageVisitor {
 
 #[cfg(test)]
 mod test {
-    use super::types::{CallOptions, ClientRoles, ErrorDetails, ErrorType, EventDetails,
-                       HelloDetails, InvocationDetails, PublishOptions, Reason, RegisterOptions,
-                       ResultDetails, RouterRoles, SubscribeOptions, Value, WelcomeDetails,
-                       YieldOptions, URI};
+    use super::types::{CallOptions, CancelMode, CancelOptions, ClientRoles, ErrorDetails,
+                       ErrorType, EventDetails, HelloDetails, InterruptOptions,
+                       InvocationDetails, PublishOptions, Reason, RegisterOptions, ResultDetails,
+                       RouterRoles, SubscribeOptions, Value, WelcomeDetails, YieldOptions, URI};
     use super::Message;
     use rmp_serde::Deserializer as RMPDeserializer;
     use rmp_serde::Serializer;
     use serde::{Deserialize, Serialize};
+    use serde_cbor;
     use serde_json;
     use std::collections::HashMap;
     use utils::StructMapWriter;
@@ -559,6 +636,8 @@ mod test {
             let mut de = RMPDeserializer::new(&buf[..]);
             let new_message: Message = Deserialize::deserialize(&mut de).unwrap();
             assert_eq!(new_message, message);
+            let buf = serde_cbor::to_vec(&message).unwrap();
+            assert_eq!(serde_cbor::from_slice::<Message>(&buf).unwrap(), message);
         }};
     }
 
@@ -570,7 +649,14 @@ mod test {
         );
         two_way_test!(
             Message::Hello(URI::new("ca.dal.wamp.test"), HelloDetails::new_with_agent(ClientRoles::new(), "dal_wamp")),
-            "[1,\"ca.dal.wamp.test\",{\"agent\":\"dal_wamp\",\"roles\":{\"publisher\":{\"features\":{}},\"subscriber\":{\"features\":{\"pattern_based_subscription\":true}},\"caller\":{\"features\":{}},\"callee\":{\"features\":{}}}}]"
+            "[1,\"ca.dal.wamp.test\",{\"agent\":\"dal_wamp\",\"roles\":{\"publisher\":{\"features\":{\"publisher_identification\":true,\"subscriber_blackwhite_listing\":true,\"publisher_exclusion\":true,\"payload_passthru_mode\":true}},\"subscriber\":{\"features\":{\"publisher_identification\":true,\"pattern_based_subscription\":true,\"publication_trustlevels\":true,\"payload_passthru_mode\":true}},\"caller\":{\"features\":{\"caller_identification\":true,\"call_timeout\":true,\"call_canceling\":true,\"progressive_call_results\":true,\"payload_passthru_mode\":true}},\"callee\":{\"features\":{\"caller_identification\":true,\"pattern_based_registration\":true,\"call_timeout\":true,\"call_canceling\":true,\"progressive_call_results\":true,\"payload_passthru_mode\":true}}}}]"
+        );
+        let mut details = HelloDetails::new(ClientRoles::new_basic());
+        details.authmethods = vec!["wampcra".to_string()];
+        details.authid = Some("joe".to_string());
+        two_way_test!(
+            Message::Hello(URI::new("ca.dal.wamp.test"), details),
+            "[1,\"ca.dal.wamp.test\",{\"roles\":{\"publisher\":{\"features\":{}},\"subscriber\":{\"features\":{}},\"caller\":{\"features\":{}},\"callee\":{\"features\":{}}},\"authmethods\":[\"wampcra\"],\"authid\":\"joe\"}]"
         )
     }
 
@@ -582,10 +668,62 @@ mod test {
         );
         two_way_test!(
             Message::Welcome(493782, WelcomeDetails::new_with_agent(RouterRoles::new(), "dal_wamp")),
-            "[2,493782,{\"agent\":\"dal_wamp\",\"roles\":{\"dealer\":{\"features\":{\"pattern_based_registration\":true}},\"broker\":{\"features\":{\"pattern_based_subscription\":true}}}}]"
+            "[2,493782,{\"agent\":\"dal_wamp\",\"roles\":{\"dealer\":{\"features\":{\"caller_identification\":true,\"pattern_based_registration\":true,\"registration_meta_api\":true,\"shared_registration\":true,\"session_meta_api\":true,\"call_timeout\":true,\"call_canceling\":true,\"progressive_call_results\":true,\"testament_meta_api\":true,\"payload_passthru_mode\":true}},\"broker\":{\"features\":{\"publisher_identification\":true,\"publication_trustlevels\":true,\"pattern_based_subscription\":true,\"subscription_meta_api\":true,\"subscriber_blackwhite_listing\":true,\"session_meta_api\":true,\"publisher_exclusion\":true,\"event_history\":true,\"event_retention\":true,\"payload_passthru_mode\":true}}}}]"
+        );
+        let mut details = WelcomeDetails::new(RouterRoles::new_basic());
+        details.authid = Some("joe".to_string());
+        details.authrole = Some("user".to_string());
+        details.authmethod = Some("wampcra".to_string());
+        details.authprovider = Some("static".to_string());
+        two_way_test!(
+            Message::Welcome(493782, details),
+            "[2,493782,{\"roles\":{\"dealer\":{},\"broker\":{}},\"authid\":\"joe\",\"authrole\":\"user\",\"authmethod\":\"wampcra\",\"authprovider\":\"static\"}]"
+        );
+        let mut details = WelcomeDetails::new(RouterRoles::new_basic());
+        details.authmethod = Some("wamp-scram".to_string());
+        let mut authextra = HashMap::new();
+        authextra.insert(
+            "scram_server_signature".to_string(),
+            Value::String("6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=".to_string()),
+        );
+        details.authextra = Some(authextra);
+        two_way_test!(
+            Message::Welcome(493782, details),
+            "[2,493782,{\"roles\":{\"dealer\":{},\"broker\":{}},\"authmethod\":\"wamp-scram\",\"authextra\":{\"scram_server_signature\":\"6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=\"}}]"
         );
     }
 
+    #[test]
+    fn inspecting_router_roles() {
+        let roles = RouterRoles::new();
+        assert!(roles.broker.is_some());
+        assert!(roles.dealer.is_some());
+        assert!(roles.broker_features().pattern_based_subscription);
+        assert!(!RouterRoles::new_basic().broker_features().pattern_based_subscription);
+    }
+
+    #[test]
+    fn parsing_announced_features() {
+        let message: Message = serde_json::from_str(
+            "[2,1,{\"roles\":{\"broker\":{\"features\":{\"event_retention\":true,\
+             \"x_acme_feature\":true}}}}]",
+        ).unwrap();
+        let roles = match message {
+            Message::Welcome(_, ref details) => details.roles().clone(),
+            _ => panic!("Expected a welcome message"),
+        };
+        assert!(roles.broker_features().event_retention);
+        assert!(!roles.broker_features().pattern_based_subscription);
+        // A role the router did not announce is absent, rather than announced without features
+        assert!(roles.dealer.is_none());
+        assert!(!roles.dealer_features().call_canceling);
+
+        let details: HelloDetails =
+            serde_json::from_str("{\"roles\":{\"caller\":{\"features\":{}}}}").unwrap();
+        assert!(!details.roles().caller.features.progressive_call_results);
+        assert!(!details.roles().subscriber.features.pattern_based_subscription);
+    }
+
     #[test]
     fn serialize_abort() {
         two_way_test!(
@@ -601,6 +739,30 @@ mod test {
         );
     }
 
+    #[test]
+    fn serialize_challenge() {
+        let mut extra = HashMap::new();
+        extra.insert(
+            "challenge".to_string(),
+            Value::String("{\"nonce\":\"LHRTC9zeOIrt_9U3\"}".to_string()),
+        );
+        two_way_test!(
+            Message::Challenge("wampcra".to_string(), extra),
+            "[4,\"wampcra\",{\"challenge\":\"{\\\"nonce\\\":\\\"LHRTC9zeOIrt_9U3\\\"}\"}]"
+        );
+    }
+
+    #[test]
+    fn serialize_authenticate() {
+        two_way_test!(
+            Message::Authenticate(
+                "gir1mSx+deCDUV7wRM5SGIn/+R/ClqLZuH4m7FJeBVI=".to_string(),
+                HashMap::new()
+            ),
+            "[5,\"gir1mSx+deCDUV7wRM5SGIn/+R/ClqLZuH4m7FJeBVI=\",{}]"
+        );
+    }
+
     #[test]
     fn serialize_goodbye() {
         two_way_test!(
@@ -664,6 +826,13 @@ mod test {
                 URI::new("ca.dal.test.the_sub")
             ),
             "[32,58944,{},\"ca.dal.test.the_sub\"]"
+        );
+
+        let mut options = SubscribeOptions::new();
+        options.get_retained = true;
+        two_way_test!(
+            Message::Subscribe(58944, options, URI::new("ca.dal.test.the_sub")),
+            "[32,58944,{\"get_retained\":true},\"ca.dal.test.the_sub\"]"
         )
     }
 
@@ -716,6 +885,34 @@ mod test {
                 Some(kwargs)
             ),
             "[16,3243542,{\"acknowledge\":true},\"ca.dal.test.topic3\",[],{\"key1\":[-5]}]"
+        );
+
+        let mut options = PublishOptions::new(false);
+        options.disclose_me = true;
+        two_way_test!(
+            Message::Publish(
+                453453,
+                options,
+                URI::new("ca.dal.test.topic1"),
+                None,
+                None
+            ),
+            "[16,453453,{\"disclose_me\":true},\"ca.dal.test.topic1\"]"
+        );
+
+        let mut options = PublishOptions::new(false);
+        options.exclude_me = Some(false);
+        options.exclude = Some(vec![23, 45]);
+        options.eligible_authrole = Some(vec!["admin".to_string()]);
+        two_way_test!(
+            Message::Publish(
+                453453,
+                options,
+                URI::new("ca.dal.test.topic1"),
+                None,
+                None
+            ),
+            "[16,453453,{\"exclude_me\":false,\"exclude\":[23,45],\"eligible_authrole\":[\"admin\"]},\"ca.dal.test.topic1\"]"
         )
     }
 
@@ -752,6 +949,38 @@ mod test {
                 Some(kwargs)
             ),
             "[36,65675,587495,{},[],{\"key1\":[-5]}]"
+        );
+
+        let mut details = EventDetails::new();
+        details.set_publisher(3335656, Some("joe".to_string()), None);
+        details.set_trustlevel(Some(2));
+        two_way_test!(
+            Message::Event(65675, 587495, details, None, None),
+            "[36,65675,587495,{\"publisher\":3335656,\"publisher_authid\":\"joe\",\"trustlevel\":2}]"
+        );
+
+        let mut details = EventDetails::new();
+        details.ppt_scheme = Some("wamp".to_string());
+        details.ppt_serializer = Some("json".to_string());
+        details.ppt_cipher = Some("aes256gcm".to_string());
+        details.ppt_keyid = Some("telemetry".to_string());
+        two_way_test!(
+            Message::Event(
+                65675,
+                587495,
+                details,
+                Some(vec![Value::Binary(vec![0, 1, 254, 255])]),
+                None
+            ),
+            "[36,65675,587495,{\"ppt_scheme\":\"wamp\",\"ppt_serializer\":\"json\",\
+             \"ppt_cipher\":\"aes256gcm\",\"ppt_keyid\":\"telemetry\"},[\"\\u0000AAH+/w==\"]]"
+        );
+
+        let mut details = EventDetails::new();
+        details.retained = true;
+        two_way_test!(
+            Message::Event(65675, 587495, details, None, None),
+            "[36,65675,587495,{\"retained\":true}]"
         )
     }
 
@@ -821,9 +1050,46 @@ mod test {
                 Some(kwargs)
             ),
             "[48,764346,{},\"com.myapp.compute\",[],{\"key1\":[5]}]"
+        );
+
+        let mut options = CallOptions::new();
+        options.receive_progress = true;
+        two_way_test!(
+            Message::Call(7814135, options, URI::new("com.myapp.ping"), None, None),
+            "[48,7814135,{\"receive_progress\":true},\"com.myapp.ping\"]"
+        );
+
+        let mut options = CallOptions::new();
+        options.timeout = 5000;
+        two_way_test!(
+            Message::Call(7814135, options, URI::new("com.myapp.ping"), None, None),
+            "[48,7814135,{\"timeout\":5000},\"com.myapp.ping\"]"
+        );
+
+        let mut options = CallOptions::new();
+        options.disclose_me = true;
+        two_way_test!(
+            Message::Call(7814135, options, URI::new("com.myapp.ping"), None, None),
+            "[48,7814135,{\"disclose_me\":true},\"com.myapp.ping\"]"
         )
     }
 
+    #[test]
+    fn serialize_cancel() {
+        two_way_test!(
+            Message::Cancel(7814135, CancelOptions::new(CancelMode::Kill)),
+            "[49,7814135,{\"mode\":\"kill\"}]"
+        );
+        two_way_test!(
+            Message::Cancel(7814135, CancelOptions::new(CancelMode::KillNoWait)),
+            "[49,7814135,{\"mode\":\"killnowait\"}]"
+        );
+        assert_eq!(
+            serde_json::from_str::<Message>("[49,7814135,{}]").unwrap(),
+            Message::Cancel(7814135, CancelOptions::new(CancelMode::Kill))
+        );
+    }
+
     #[test]
     fn serialize_invocation() {
         // two_way_test!(
@@ -855,9 +1121,37 @@ mod test {
                 Some(kwargs)
             ),
             "[68,764346,9823526,{},[],{\"key1\":[5]}]"
+        );
+
+        let mut details = InvocationDetails::new();
+        details.receive_progress = true;
+        two_way_test!(
+            Message::Invocation(764346, 9823526, details, Some(Vec::new()), None),
+            "[68,764346,9823526,{\"receive_progress\":true},[]]"
+        );
+
+        let mut details = InvocationDetails::new();
+        details.caller = Some(3335656);
+        details.caller_authid = Some("joe".to_string());
+        details.caller_authrole = Some("user".to_string());
+        two_way_test!(
+            Message::Invocation(764346, 9823526, details, Some(Vec::new()), None),
+            "[68,764346,9823526,{\"caller\":3335656,\"caller_authid\":\"joe\",\"caller_authrole\":\"user\"},[]]"
         )
     }
 
+    #[test]
+    fn serialize_interrupt() {
+        two_way_test!(
+            Message::Interrupt(6131533, InterruptOptions::new(CancelMode::Kill)),
+            "[69,6131533,{\"mode\":\"kill\"}]"
+        );
+        two_way_test!(
+            Message::Interrupt(6131533, InterruptOptions::new(CancelMode::KillNoWait)),
+            "[69,6131533,{\"mode\":\"killnowait\"}]"
+        );
+    }
+
     #[test]
     fn serialize_yield() {
         two_way_test!(
@@ -882,6 +1176,13 @@ mod test {
         two_way_test!(
             Message::Yield(6131533, YieldOptions::new(), Some(Vec::new()), Some(kwargs)),
             "[70,6131533,{},[],{\"key1\":[5]}]"
+        );
+
+        let mut options = YieldOptions::new();
+        options.progress = true;
+        two_way_test!(
+            Message::Yield(6131533, options, Some(vec![Value::UnsignedInteger(1)]), None),
+            "[70,6131533,{\"progress\":true},[1]]"
         )
     }
 
@@ -906,6 +1207,13 @@ mod test {
         two_way_test!(
             Message::Result(764346, ResultDetails::new(), Some(Vec::new()), Some(kwargs)),
             "[50,764346,{},[],{\"key1\":[8.6]}]"
+        );
+
+        let mut details = ResultDetails::new();
+        details.progress = true;
+        two_way_test!(
+            Message::Result(764346, details, Some(vec![Value::UnsignedInteger(1)]), None),
+            "[50,764346,{\"progress\":true},[1]]"
         )
     }
 
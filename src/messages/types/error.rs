@@ -24,6 +24,10 @@ pub enum Reason {
     OptionDisallowedDiscloseMe,
     NetworkFailure,
     NormalClose,
+    Timeout,
+    NoSuchSession,
+    NoAuthMethod,
+    AuthenticationFailed,
     CustomReason(URI),
 }
 
@@ -101,6 +105,10 @@ impl Reason {
             Reason::OptionDisallowedDiscloseMe => "wamp.error.option-disallowed.disclose_me",
             Reason::NetworkFailure => "wamp.error.network_failure",
             Reason::NormalClose => "wamp.close.normal",
+            Reason::Timeout => "wamp.error.timeout",
+            Reason::NoSuchSession => "wamp.error.no_such_session",
+            Reason::NoAuthMethod => "wamp.error.no_auth_method",
+            Reason::AuthenticationFailed => "wamp.error.authentication_failed",
             Reason::CustomReason(ref reason) => &reason.uri,
         }
     }
@@ -166,6 +174,10 @@ impl<'de> serde::de::Visitor<'de> for ReasonVisitor {
             "wamp.error.option-disallowed.disclose_me" => Ok(Reason::OptionDisallowedDiscloseMe),
             "wamp.error.network_failure" => Ok(Reason::NetworkFailure),
             "wamp.close.normal" => Ok(Reason::NormalClose),
+            "wamp.error.timeout" => Ok(Reason::Timeout),
+            "wamp.error.no_such_session" => Ok(Reason::NoSuchSession),
+            "wamp.error.no_auth_method" => Ok(Reason::NoAuthMethod),
+            "wamp.error.authentication_failed" => Ok(Reason::AuthenticationFailed),
             x => Ok(Reason::CustomReason(URI::new(x))),
         }
     }
@@ -15,6 +15,14 @@ fn is_not(b: &bool) -> bool {
     !*b
 }
 
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    *t == T::default()
+}
+
 /**************************
          Structs
 **************************/
@@ -45,12 +53,24 @@ pub enum InvocationPolicy {
     Last,
 }
 
+/// The ways in which a pending call can be cancelled
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum CancelMode {
+    /// The dealer stops waiting for the callee, but the callee is not told about the cancellation
+    Skip,
+    /// The callee is interrupted, and the caller receives an error once the callee has responded
+    Kill,
+    /// The callee is interrupted, and the caller receives an error immediately
+    KillNoWait,
+}
+
 /**************************
         Visitors
 **************************/
 
 struct MatchingPolicyVisitor;
 struct InvocationPolicyVisitor;
+struct CancelModeVisitor;
 
 impl MatchingPolicy {
     #[inline]
@@ -80,6 +100,13 @@ impl Default for InvocationPolicy {
     }
 }
 
+impl Default for CancelMode {
+    #[inline]
+    fn default() -> CancelMode {
+        CancelMode::Kill
+    }
+}
+
 /*-------------------------
        MatchingPolicy
 -------------------------*/
@@ -180,3 +207,51 @@ impl<'de> serde::de::Visitor<'de> for InvocationPolicyVisitor {
         }
     }
 }
+
+/*-------------------------
+         CancelMode
+-------------------------*/
+
+impl serde::Serialize for CancelMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let ser_str = match *self {
+            CancelMode::Skip => "skip",
+            CancelMode::Kill => "kill",
+            CancelMode::KillNoWait => "killnowait",
+        };
+        serializer.serialize_str(ser_str)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for CancelMode {
+    fn deserialize<D>(deserializer: D) -> Result<CancelMode, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(CancelModeVisitor)
+    }
+}
+
+impl<'de> serde::de::Visitor<'de> for CancelModeVisitor {
+    type Value = CancelMode;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("cancel mode for a call")
+    }
+
+    #[inline]
+    fn visit_str<E>(self, value: &str) -> Result<CancelMode, E>
+    where
+        E: serde::de::Error,
+    {
+        match value {
+            "skip" => Ok(CancelMode::Skip),
+            "kill" => Ok(CancelMode::Kill),
+            "killnowait" => Ok(CancelMode::KillNoWait),
+            x => Err(serde::de::Error::custom(format!("Invalid cancel mode: {}", x))),
+        }
+    }
+}
@@ -1,17 +1,43 @@
-use super::{is_not, ClientRoles, InvocationPolicy, MatchingPolicy, RouterRoles, URI};
+use super::{is_not, is_zero, CancelMode, ClientRoles, InvocationPolicy, MatchingPolicy,
+            RouterRoles, URI};
+use {Dict, ID};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
 pub struct HelloDetails {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     agent: Option<String>,
     roles: ClientRoles,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authmethods: Vec<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authextra: Option<Dict>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
 pub struct WelcomeDetails {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     agent: Option<String>,
     roles: RouterRoles,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authrole: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authmethod: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authprovider: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authextra: Option<Dict>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
@@ -24,12 +50,59 @@ pub struct ErrorDetails {
 pub struct SubscribeOptions {
     #[serde(default, rename = "match", skip_serializing_if = "MatchingPolicy::is_strict")]
     pub pattern_match: MatchingPolicy,
+
+    /// Whether the broker should send the retained events of matching topics upon subscription.
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub get_retained: bool,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
 pub struct PublishOptions {
     #[serde(default, skip_serializing_if = "is_not")]
     acknowledge: bool,
+
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub disclose_me: bool,
+
+    /// Whether the broker should keep this event as the retained event of its topic.
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub retain: bool,
+
+    /// Whether the publisher should be excluded from receiving the event.  Defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_me: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<ID>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_authid: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude_authrole: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eligible: Option<Vec<ID>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eligible_authid: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eligible_authrole: Option<Vec<String>>,
+
+    /// The payload passthru scheme.  When it is set, the arguments hold a single binary value
+    /// that the router forwards without interpreting it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_scheme: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_serializer: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_cipher: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_keyid: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
@@ -42,37 +115,148 @@ pub struct RegisterOptions {
 }
 
 #[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
-pub struct CallOptions {}
+pub struct CallOptions {
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub receive_progress: bool,
+
+    /// The number of milliseconds the dealer should wait for a result before cancelling the
+    /// call.  Zero means the call never times out.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub timeout: u64,
+
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub disclose_me: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_scheme: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_serializer: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_cipher: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_keyid: Option<String>,
+}
 
 #[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
-pub struct YieldOptions {}
+pub struct YieldOptions {
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub progress: bool,
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_scheme: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_serializer: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_cipher: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_keyid: Option<String>,
+}
+
+#[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct CancelOptions {
+    #[serde(default)]
+    pub mode: CancelMode,
+}
+
+#[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct InterruptOptions {
+    #[serde(default)]
+    pub mode: CancelMode,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default, Clone)]
 pub struct EventDetails {
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    publisher: Option<String>,
+    publisher: Option<ID>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    publisher_authid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    publisher_authrole: Option<String>,
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     trustlevel: Option<u64>,
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub topic: Option<URI>,
+
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub retained: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_scheme: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_serializer: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_cipher: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_keyid: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
 pub struct InvocationDetails {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub procedure: Option<URI>,
+
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub receive_progress: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<ID>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_authid: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_authrole: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_scheme: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_serializer: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_cipher: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_keyid: Option<String>,
 }
 
 #[derive(PartialEq, Debug, Default, Serialize, Deserialize)]
-pub struct ResultDetails {}
+pub struct ResultDetails {
+    #[serde(default, skip_serializing_if = "is_not")]
+    pub progress: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_scheme: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_serializer: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_cipher: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ppt_keyid: Option<String>,
+}
 
 impl HelloDetails {
     pub fn new(roles: ClientRoles) -> HelloDetails {
         HelloDetails {
             roles: roles,
             agent: None,
+            ..Default::default()
         }
     }
 
@@ -80,8 +264,13 @@ impl HelloDetails {
         HelloDetails {
             roles: roles,
             agent: Some(agent.to_string()),
+            ..Default::default()
         }
     }
+
+    pub fn roles(&self) -> &ClientRoles {
+        &self.roles
+    }
 }
 
 impl WelcomeDetails {
@@ -89,6 +278,7 @@ impl WelcomeDetails {
         WelcomeDetails {
             roles: roles,
             agent: None,
+            ..Default::default()
         }
     }
 
@@ -96,8 +286,13 @@ impl WelcomeDetails {
         WelcomeDetails {
             roles: roles,
             agent: Some(agent.to_string()),
+            ..Default::default()
         }
     }
+
+    pub fn roles(&self) -> &RouterRoles {
+        &self.roles
+    }
 }
 
 impl ErrorDetails {
@@ -116,6 +311,7 @@ impl SubscribeOptions {
     pub fn new() -> SubscribeOptions {
         SubscribeOptions {
             pattern_match: MatchingPolicy::Strict,
+            get_retained: false,
         }
     }
 }
@@ -124,12 +320,17 @@ impl PublishOptions {
     pub fn new(acknowledge: bool) -> PublishOptions {
         PublishOptions {
             acknowledge: acknowledge,
+            ..Default::default()
         }
     }
 
     pub fn should_acknowledge(&self) -> bool {
         self.acknowledge
     }
+
+    pub fn excludes_me(&self) -> bool {
+        self.exclude_me.unwrap_or(true)
+    }
 }
 
 impl RegisterOptions {
@@ -143,13 +344,39 @@ impl RegisterOptions {
 
 impl CallOptions {
     pub fn new() -> CallOptions {
-        CallOptions {}
+        CallOptions {
+            receive_progress: false,
+            timeout: 0,
+            disclose_me: false,
+            ppt_scheme: None,
+            ppt_serializer: None,
+            ppt_cipher: None,
+            ppt_keyid: None,
+        }
     }
 }
 
 impl YieldOptions {
     pub fn new() -> YieldOptions {
-        YieldOptions {}
+        YieldOptions {
+            progress: false,
+            ppt_scheme: None,
+            ppt_serializer: None,
+            ppt_cipher: None,
+            ppt_keyid: None,
+        }
+    }
+}
+
+impl CancelOptions {
+    pub fn new(mode: CancelMode) -> CancelOptions {
+        CancelOptions { mode: mode }
+    }
+}
+
+impl InterruptOptions {
+    pub fn new(mode: CancelMode) -> InterruptOptions {
+        InterruptOptions { mode: mode }
     }
 }
 
@@ -157,28 +384,93 @@ impl EventDetails {
     pub fn new() -> EventDetails {
         EventDetails {
             publisher: None,
+            publisher_authid: None,
+            publisher_authrole: None,
             trustlevel: None,
             topic: None,
+            retained: false,
+            ppt_scheme: None,
+            ppt_serializer: None,
+            ppt_cipher: None,
+            ppt_keyid: None,
         }
     }
 
     pub fn new_with_topic(topic: URI) -> EventDetails {
         EventDetails {
             publisher: None,
+            publisher_authid: None,
+            publisher_authrole: None,
             trustlevel: None,
             topic: Some(topic),
+            retained: false,
+            ppt_scheme: None,
+            ppt_serializer: None,
+            ppt_cipher: None,
+            ppt_keyid: None,
         }
     }
+
+    pub fn set_publisher(&mut self, id: ID, authid: Option<String>, authrole: Option<String>) {
+        self.publisher = Some(id);
+        self.publisher_authid = authid;
+        self.publisher_authrole = authrole;
+    }
+
+    #[inline]
+    pub fn set_trustlevel(&mut self, trustlevel: Option<u64>) {
+        self.trustlevel = trustlevel;
+    }
+
+    /// The session id of the publisher, if the publisher chose to disclose it.
+    #[inline]
+    pub fn publisher(&self) -> Option<ID> {
+        self.publisher
+    }
+
+    /// The authentication id of the publisher, if the publisher chose to disclose it.
+    #[inline]
+    pub fn publisher_authid(&self) -> Option<&str> {
+        self.publisher_authid.as_ref().map(|authid| &authid[..])
+    }
+
+    /// The authentication role of the publisher, if the publisher chose to disclose it.
+    #[inline]
+    pub fn publisher_authrole(&self) -> Option<&str> {
+        self.publisher_authrole.as_ref().map(|authrole| &authrole[..])
+    }
+
+    /// The trust level the router assigned to this event, if any.
+    #[inline]
+    pub fn trustlevel(&self) -> Option<u64> {
+        self.trustlevel
+    }
 }
 
 impl InvocationDetails {
     pub fn new() -> InvocationDetails {
-        InvocationDetails { procedure: None }
+        InvocationDetails {
+            procedure: None,
+            receive_progress: false,
+            caller: None,
+            caller_authid: None,
+            caller_authrole: None,
+            ppt_scheme: None,
+            ppt_serializer: None,
+            ppt_cipher: None,
+            ppt_keyid: None,
+        }
     }
 }
 
 impl ResultDetails {
     pub fn new() -> ResultDetails {
-        ResultDetails {}
+        ResultDetails {
+            progress: false,
+            ppt_scheme: None,
+            ppt_serializer: None,
+            ppt_cipher: None,
+            ppt_keyid: None,
+        }
     }
 }
@@ -1,74 +1,183 @@
-use super::is_not;
-use std::collections::HashMap;
+use super::{is_default, is_not};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ClientRoles {
+    #[serde(default)]
     pub publisher: PublisherRole,
+    #[serde(default)]
     pub subscriber: SubscriberRole,
+    #[serde(default)]
     pub caller: CallerRole,
+    #[serde(default)]
     pub callee: CalleeRole,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct RouterRoles {
-    pub dealer: DealerRole,
-    pub broker: BrokerRole,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dealer: Option<DealerRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broker: Option<BrokerRole>,
 }
 
 /**************************
           Roles
 **************************/
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct PublisherRole {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    features: Option<HashMap<String, bool>>,
+    #[serde(default)]
+    pub features: PublisherFeatures,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct SubscriberRole {
+    #[serde(default)]
+    pub features: SubscriberFeatures,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct CallerRole {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    features: Option<HashMap<String, bool>>,
+    #[serde(default)]
+    pub features: CallerFeatures,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct CalleeRole {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    features: Option<HashMap<String, bool>>,
+    #[serde(default)]
+    pub features: CalleeFeatures,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub struct SubscriberRole {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    features: Option<SubscriberFeatures>,
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct DealerRole {
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub features: DealerFeatures,
 }
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct BrokerRole {
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub features: BrokerFeatures,
+}
+
+/**************************
+        Features
+**************************/
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct PublisherFeatures {
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub publisher_identification: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub subscriber_blackwhite_listing: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub publisher_exclusion: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub payload_passthru_mode: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct SubscriberFeatures {
     #[serde(skip_serializing_if = "is_not", default)]
-    pattern_based_subscription: bool,
+    pub publisher_identification: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub pattern_based_subscription: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub publication_trustlevels: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub event_history: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub subscription_revocation: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub payload_passthru_mode: bool,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub struct DealerRole {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    features: Option<DealerFeatures>,
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct CallerFeatures {
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub caller_identification: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_timeout: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_canceling: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub progressive_call_results: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub payload_passthru_mode: bool,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-pub struct BrokerRole {
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    features: Option<BrokerFeatures>,
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct CalleeFeatures {
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub caller_identification: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_trustlevels: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub pattern_based_registration: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub shared_registration: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_timeout: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_canceling: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub progressive_call_results: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub registration_revocation: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub payload_passthru_mode: bool,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct DealerFeatures {
     #[serde(skip_serializing_if = "is_not", default)]
-    pattern_based_registration: bool,
+    pub caller_identification: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_trustlevels: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub pattern_based_registration: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub registration_meta_api: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub shared_registration: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub session_meta_api: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_timeout: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub call_canceling: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub progressive_call_results: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub registration_revocation: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub testament_meta_api: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub payload_passthru_mode: bool,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct BrokerFeatures {
     #[serde(skip_serializing_if = "is_not", default)]
-    pattern_based_subscription: bool,
+    pub publisher_identification: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub publication_trustlevels: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub pattern_based_subscription: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub subscription_meta_api: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub subscriber_blackwhite_listing: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub session_meta_api: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub publisher_exclusion: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub event_history: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub event_retention: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub subscription_revocation: bool,
+    #[serde(skip_serializing_if = "is_not", default)]
+    pub payload_passthru_mode: bool,
 }
 
 /**************************
@@ -76,48 +185,116 @@ pub struct BrokerFeatures {
 **************************/
 
 impl RouterRoles {
+    /// Announces every feature the router implements.  The router narrows this down to what is
+    /// enabled in each realm.
     #[inline]
     pub fn new() -> RouterRoles {
         RouterRoles {
-            broker: BrokerRole {
-                features: Some(BrokerFeatures {
+            broker: Some(BrokerRole {
+                features: BrokerFeatures {
+                    publisher_identification: true,
+                    publication_trustlevels: true,
                     pattern_based_subscription: true,
-                }),
-            },
-            dealer: DealerRole {
-                features: Some(DealerFeatures {
+                    subscription_meta_api: true,
+                    subscriber_blackwhite_listing: true,
+                    session_meta_api: true,
+                    publisher_exclusion: true,
+                    event_history: true,
+                    event_retention: true,
+                    subscription_revocation: false,
+                    payload_passthru_mode: true,
+                },
+            }),
+            dealer: Some(DealerRole {
+                features: DealerFeatures {
+                    caller_identification: true,
+                    call_trustlevels: false,
                     pattern_based_registration: true,
-                }),
-            },
+                    registration_meta_api: true,
+                    shared_registration: true,
+                    session_meta_api: true,
+                    call_timeout: true,
+                    call_canceling: true,
+                    progressive_call_results: true,
+                    registration_revocation: false,
+                    testament_meta_api: true,
+                    payload_passthru_mode: true,
+                },
+            }),
         }
     }
 
     #[inline]
     pub fn new_basic() -> RouterRoles {
         RouterRoles {
-            broker: BrokerRole { features: None },
-            dealer: DealerRole { features: None },
+            broker: Some(BrokerRole::default()),
+            dealer: Some(DealerRole::default()),
         }
     }
+
+    /// The broker features the router announced, all of them off if it did not announce the
+    /// broker role.
+    pub fn broker_features(&self) -> BrokerFeatures {
+        self.broker
+            .as_ref()
+            .map(|broker| broker.features.clone())
+            .unwrap_or_default()
+    }
+
+    /// The dealer features the router announced, all of them off if it did not announce the
+    /// dealer role.
+    pub fn dealer_features(&self) -> DealerFeatures {
+        self.dealer
+            .as_ref()
+            .map(|dealer| dealer.features.clone())
+            .unwrap_or_default()
+    }
 }
 
 impl ClientRoles {
+    /// Announces every feature the client implements.
     #[inline]
     pub fn new() -> ClientRoles {
         ClientRoles {
             publisher: PublisherRole {
-                features: Some(HashMap::new()),
+                features: PublisherFeatures {
+                    publisher_identification: true,
+                    subscriber_blackwhite_listing: true,
+                    publisher_exclusion: true,
+                    payload_passthru_mode: true,
+                },
             },
             subscriber: SubscriberRole {
-                features: Some(SubscriberFeatures {
+                features: SubscriberFeatures {
+                    publisher_identification: true,
                     pattern_based_subscription: true,
-                }),
+                    publication_trustlevels: true,
+                    event_history: false,
+                    subscription_revocation: false,
+                    payload_passthru_mode: true,
+                },
             },
             caller: CallerRole {
-                features: Some(HashMap::new()),
+                features: CallerFeatures {
+                    caller_identification: true,
+                    call_timeout: true,
+                    call_canceling: true,
+                    progressive_call_results: true,
+                    payload_passthru_mode: true,
+                },
             },
             callee: CalleeRole {
-                features: Some(HashMap::new()),
+                features: CalleeFeatures {
+                    caller_identification: true,
+                    call_trustlevels: false,
+                    pattern_based_registration: true,
+                    shared_registration: false,
+                    call_timeout: true,
+                    call_canceling: true,
+                    progressive_call_results: true,
+                    registration_revocation: false,
+                    payload_passthru_mode: true,
+                },
             },
         }
     }
@@ -125,20 +302,10 @@ impl ClientRoles {
     #[inline]
     pub fn new_basic() -> ClientRoles {
         ClientRoles {
-            publisher: PublisherRole {
-                features: Some(HashMap::new()),
-            },
-            subscriber: SubscriberRole {
-                features: Some(SubscriberFeatures {
-                    pattern_based_subscription: false,
-                }),
-            },
-            caller: CallerRole {
-                features: Some(HashMap::new()),
-            },
-            callee: CalleeRole {
-                features: Some(HashMap::new()),
-            },
+            publisher: PublisherRole::default(),
+            subscriber: SubscriberRole::default(),
+            caller: CallerRole::default(),
+            callee: CalleeRole::default(),
         }
     }
 }
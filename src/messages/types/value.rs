@@ -1,4 +1,5 @@
 use super::{CallError, Reason};
+use base64;
 use itertools::Itertools;
 use serde;
 use std::collections::HashMap;
@@ -33,6 +34,9 @@ pub enum Value {
     String(String),
     List(List),
     Boolean(bool),
+    /// Opaque bytes, such as an encrypted payload.  JSON carries these as base64 strings that
+    /// start with a null character.
+    Binary(Vec<u8>),
 }
 
 struct URIVisitor;
@@ -186,6 +190,7 @@ impl Value {
                 result
             }
             Value::Boolean(b) => b.to_string(),
+            Value::Binary(ref b) => format!("<{} bytes>", b.len()),
         }
     }
 }
@@ -203,7 +208,30 @@ impl<'de> serde::de::Visitor<'de> for ValueVisitor {
     where
         E: serde::de::Error,
     {
-        Ok(Value::String(value.to_string()))
+        if value.starts_with('\0') {
+            match base64::decode(&value[1..]) {
+                Ok(bytes) => Ok(Value::Binary(bytes)),
+                Err(_) => Err(E::custom("Invalid base64 in binary string")),
+            }
+        } else {
+            Ok(Value::String(value.to_string()))
+        }
+    }
+
+    #[inline]
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Value::Binary(value.to_vec()))
+    }
+
+    #[inline]
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(Value::Binary(value))
     }
 
     #[inline]
@@ -288,6 +316,15 @@ impl serde::Serialize for Value {
             Value::Float(f) => serializer.serialize_f64(f),
             Value::List(ref list) => list.serialize(serializer),
             Value::Boolean(b) => serializer.serialize_bool(b),
+            Value::Binary(ref b) => {
+                if serializer.is_human_readable() {
+                    let mut encoded = String::from("\0");
+                    encoded.push_str(&base64::encode(b));
+                    serializer.serialize_str(&encoded)
+                } else {
+                    serializer.serialize_bytes(b)
+                }
+            }
         }
     }
 }
@@ -0,0 +1,117 @@
+//! Contains the end to end encryption of payloads that the router forwards without reading them,
+//! using the payload passthru mode of the advanced profile.
+use auth;
+use messages::{Dict, List, Value};
+use ring::aead::{self, OpeningKey, SealingKey, AES_256_GCM};
+use serde_json;
+use {Error, ErrorKind, WampResult};
+
+pub const PPT_SCHEME: &'static str = "wamp";
+pub const PPT_SERIALIZER: &'static str = "json";
+pub const PPT_CIPHER: &'static str = "aes256gcm";
+
+const KEY_LEN: usize = 32;
+
+/// A symmetric key shared by the publishers and subscribers, or the callers and callees, of a
+/// topic or procedure.  Payloads encrypted with it are opaque to the router.
+///
+/// The arguments and keyword arguments are serialized together as JSON and encrypted with
+/// AES-256-GCM.  The resulting payload is a single binary argument holding the nonce followed by
+/// the ciphertext and tag.
+pub struct PayloadKey {
+    keyid: String,
+    key: Vec<u8>,
+}
+
+impl PayloadKey {
+    /// Creates a key from 32 bytes of secret key material.  The key id tells the receiver which
+    /// key to decrypt with.
+    pub fn new(keyid: &str, key: &[u8]) -> WampResult<PayloadKey> {
+        if key.len() != KEY_LEN {
+            return Err(Error::new(ErrorKind::MalformedData));
+        }
+        Ok(PayloadKey {
+            keyid: keyid.to_string(),
+            key: key.to_vec(),
+        })
+    }
+
+    #[inline]
+    pub fn keyid(&self) -> &str {
+        &self.keyid
+    }
+
+    /// Encrypts the given arguments into a payload of a single binary argument.
+    pub fn encrypt(&self, args: Option<List>, kwargs: Option<Dict>) -> WampResult<List> {
+        let payload = (args.unwrap_or_default(), kwargs.unwrap_or_default());
+        let mut in_out = match serde_json::to_vec(&payload) {
+            Ok(serialized) => serialized,
+            Err(e) => return Err(Error::new(ErrorKind::JSONError(e))),
+        };
+        let tag_len = AES_256_GCM.tag_len();
+        in_out.extend(vec![0u8; tag_len]);
+        let mut sealed = auth::random_bytes(AES_256_GCM.nonce_len());
+        let len = match SealingKey::new(&AES_256_GCM, &self.key)
+            .and_then(|key| aead::seal_in_place(&key, &sealed, &[], &mut in_out, tag_len))
+        {
+            Ok(len) => len,
+            Err(_) => return Err(Error::new(ErrorKind::MalformedData)),
+        };
+        sealed.extend_from_slice(&in_out[..len]);
+        Ok(vec![Value::Binary(sealed)])
+    }
+
+    /// Decrypts a payload created by `encrypt()` with the same key.  Fails with `MalformedData`
+    /// if the payload was not encrypted with this key or has been tampered with.
+    pub fn decrypt(&self, args: &[Value]) -> WampResult<(List, Dict)> {
+        let nonce_len = AES_256_GCM.nonce_len();
+        let sealed = match args.first() {
+            Some(&Value::Binary(ref sealed))
+                if args.len() == 1 && sealed.len() >= nonce_len + AES_256_GCM.tag_len() =>
+            {
+                sealed
+            }
+            _ => return Err(Error::new(ErrorKind::MalformedData)),
+        };
+        let (nonce, ciphertext) = sealed.split_at(nonce_len);
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = match OpeningKey::new(&AES_256_GCM, &self.key)
+            .and_then(|key| aead::open_in_place(&key, nonce, &[], 0, &mut in_out))
+        {
+            Ok(plaintext) => plaintext,
+            Err(_) => return Err(Error::new(ErrorKind::MalformedData)),
+        };
+        match serde_json::from_slice(plaintext) {
+            Ok(payload) => Ok(payload),
+            Err(e) => Err(Error::new(ErrorKind::JSONError(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PayloadKey;
+    use messages::Value;
+    use std::collections::HashMap;
+
+    #[test]
+    fn encrypting_payloads() {
+        let key = PayloadKey::new("telemetry", &[7u8; 32]).unwrap();
+        let mut kwargs = HashMap::new();
+        kwargs.insert("temperature".to_string(), Value::Float(37.2));
+        let args = vec![Value::String("ward 4".to_string())];
+        let payload = key.encrypt(Some(args.clone()), Some(kwargs.clone())).unwrap();
+        assert_eq!(payload.len(), 1);
+        assert_eq!(key.decrypt(&payload).unwrap(), (args, kwargs));
+
+        let other = PayloadKey::new("telemetry", &[8u8; 32]).unwrap();
+        assert!(other.decrypt(&payload).is_err());
+        let mut tampered = payload.clone();
+        if let Value::Binary(ref mut sealed) = tampered[0] {
+            sealed[20] ^= 1;
+        }
+        assert!(key.decrypt(&tampered).is_err());
+        assert!(key.decrypt(&[Value::Integer(1)]).is_err());
+        assert!(PayloadKey::new("short", &[7u8; 16]).is_err());
+    }
+}
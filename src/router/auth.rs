@@ -0,0 +1,556 @@
+//! Contains the credentials a realm uses to authenticate sessions, and the state of sessions
+//! that have been challenged but have not yet answered.
+use auth::{self, cryptosign, scram, wampcra};
+use messages::{HelloDetails, Reason};
+use serde_json;
+use router::ConnectionInfo;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ws::util::Token;
+use {Dict, List, Value, ID};
+
+/// The timeout token used to wake a session up once the authenticator has answered.
+pub const RESUME_AUTHENTICATION: Token = Token(2);
+
+/// How long, in milliseconds, a session waits for the authenticator before it is refused.
+pub const AUTHENTICATION_TIMEOUT: u64 = 10000;
+
+/// Checks the tickets presented by sessions that authenticate with the `ticket` method.
+pub trait TicketValidator: Send + Sync {
+    /// Returns the role the session should be given if the ticket is valid for the given authid,
+    /// or `None` if the session should be refused.
+    fn validate(&self, authid: &str, ticket: &str) -> Option<String>;
+}
+
+impl<F> TicketValidator for F
+where
+    F: Fn(&str, &str) -> Option<String> + Send + Sync,
+{
+    fn validate(&self, authid: &str, ticket: &str) -> Option<String> {
+        self(authid, ticket)
+    }
+}
+
+/// The credentials that sessions may use to join a realm.
+pub struct CredentialStore {
+    allow_anonymous: bool,
+    wampcra: HashMap<String, WampCraUser>,
+    ticket_validator: Option<Arc<TicketValidator>>,
+    cryptosign: HashMap<String, CryptosignUser>,
+    scram: HashMap<String, ScramUser>,
+}
+
+struct WampCraUser {
+    secret: String,
+    authrole: String,
+    salt: Option<Salt>,
+}
+
+struct CryptosignUser {
+    authid: String,
+    authrole: String,
+}
+
+struct ScramUser {
+    keys: scram::StoredKeys,
+    authrole: String,
+}
+
+struct Salt {
+    salt: String,
+    iterations: u32,
+    keylen: usize,
+}
+
+/// The identity a session is given once it has been authenticated.
+pub struct Identity {
+    pub authid: Option<String>,
+    pub authrole: String,
+    pub authmethod: String,
+    pub authprovider: String,
+    pub authextra: Option<Dict>,
+}
+
+/// A challenge that has been sent to a session, along with the identity the session will be
+/// given if it answers correctly.
+pub struct PendingAuth {
+    pub identity: Identity,
+    challenge: PendingChallenge,
+}
+
+enum PendingChallenge {
+    WampCra { challenge: String, secret: String },
+    Ticket { validator: Arc<TicketValidator> },
+    Cryptosign { challenge: String, public_key: String },
+    Scram {
+        auth_message: String,
+        keys: scram::StoredKeys,
+    },
+}
+
+/// A call to the realm's authenticator that has not been answered yet.
+pub struct PendingAuthentication {
+    session: Arc<Mutex<ConnectionInfo>>,
+    authenticator: Arc<Mutex<ConnectionInfo>>,
+    authid: Option<String>,
+    authmethod: String,
+    deadline: Instant,
+}
+
+/// What the router should do in response to a HELLO message.
+pub enum HelloOutcome {
+    Welcome(Identity),
+    Challenge(String, Dict, PendingAuth),
+    Abort(Reason),
+}
+
+impl CredentialStore {
+    pub fn new() -> CredentialStore {
+        CredentialStore {
+            allow_anonymous: true,
+            wampcra: HashMap::new(),
+            ticket_validator: None,
+            cryptosign: HashMap::new(),
+            scram: HashMap::new(),
+        }
+    }
+
+    pub fn set_anonymous_access(&mut self, allow: bool) {
+        self.allow_anonymous = allow;
+    }
+
+    pub fn add_wampcra_user(&mut self, authid: &str, secret: &str, authrole: &str) {
+        self.wampcra.insert(
+            authid.to_string(),
+            WampCraUser {
+                secret: secret.to_string(),
+                authrole: authrole.to_string(),
+                salt: None,
+            },
+        );
+    }
+
+    pub fn add_salted_wampcra_user(
+        &mut self,
+        authid: &str,
+        secret: &str,
+        salt: &str,
+        iterations: u32,
+        keylen: usize,
+        authrole: &str,
+    ) {
+        self.wampcra.insert(
+            authid.to_string(),
+            WampCraUser {
+                secret: wampcra::derive_key(secret, salt, iterations, keylen),
+                authrole: authrole.to_string(),
+                salt: Some(Salt {
+                    salt: salt.to_string(),
+                    iterations: iterations,
+                    keylen: keylen,
+                }),
+            },
+        );
+    }
+
+    pub fn set_ticket_validator<V>(&mut self, validator: V)
+    where
+        V: TicketValidator + 'static,
+    {
+        self.ticket_validator = Some(Arc::new(validator));
+    }
+
+    pub fn add_cryptosign_key(&mut self, public_key: &str, authid: &str, authrole: &str) {
+        self.cryptosign.insert(
+            public_key.to_lowercase(),
+            CryptosignUser {
+                authid: authid.to_string(),
+                authrole: authrole.to_string(),
+            },
+        );
+    }
+
+    /// Adds a user that can authenticate with WAMP-SCRAM.  Only the keys derived from the password
+    /// with a random salt are kept.
+    pub fn add_scram_user(&mut self, authid: &str, password: &str, authrole: &str) {
+        let salt = auth::random_bytes(16);
+        self.scram.insert(
+            authid.to_string(),
+            ScramUser {
+                keys: scram::StoredKeys::derive(password, &salt, scram::DEFAULT_ITERATIONS),
+                authrole: authrole.to_string(),
+            },
+        );
+    }
+
+    /// Decides how to authenticate a session, based on the methods it offered in its HELLO.
+    ///
+    /// The methods are tried in the order the session listed them.  A session that offers no
+    /// methods is treated as anonymous.
+    pub fn authenticate(&self, session_id: ID, details: &HelloDetails) -> HelloOutcome {
+        let anonymous = vec!["anonymous".to_string()];
+        let authmethods = if details.authmethods.is_empty() {
+            &anonymous
+        } else {
+            &details.authmethods
+        };
+        for authmethod in authmethods {
+            match &authmethod[..] {
+                "wampcra" => {
+                    if let Some(outcome) = self.challenge_wampcra(session_id, details) {
+                        return outcome;
+                    }
+                }
+                "cryptosign" => {
+                    if let Some(outcome) = self.challenge_cryptosign(details) {
+                        return outcome;
+                    }
+                }
+                "wamp-scram" => {
+                    if let Some(outcome) = self.challenge_scram(details) {
+                        return outcome;
+                    }
+                }
+                "ticket" => {
+                    if let Some(outcome) = self.challenge_ticket(details) {
+                        return outcome;
+                    }
+                }
+                "anonymous" => {
+                    if self.allow_anonymous {
+                        return HelloOutcome::Welcome(Identity {
+                            authid: None,
+                            authrole: "anonymous".to_string(),
+                            authmethod: "anonymous".to_string(),
+                            authprovider: "static".to_string(),
+                            authextra: None,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        HelloOutcome::Abort(Reason::NoAuthMethod)
+    }
+
+    fn challenge_wampcra(&self, session_id: ID, details: &HelloDetails) -> Option<HelloOutcome> {
+        let authid = match details.authid {
+            Some(ref authid) => authid,
+            None => return None,
+        };
+        let user = match self.wampcra.get(authid) {
+            Some(user) => user,
+            None => return None,
+        };
+        let mut challenge = HashMap::new();
+        challenge.insert("authid".to_string(), Value::String(authid.clone()));
+        challenge.insert("authrole".to_string(), Value::String(user.authrole.clone()));
+        challenge.insert(
+            "authmethod".to_string(),
+            Value::String("wampcra".to_string()),
+        );
+        challenge.insert(
+            "authprovider".to_string(),
+            Value::String("static".to_string()),
+        );
+        challenge.insert("nonce".to_string(), Value::String(auth::nonce()));
+        challenge.insert("session".to_string(), Value::UnsignedInteger(session_id));
+        let challenge = serde_json::to_string(&challenge).unwrap();
+
+        let mut extra = HashMap::new();
+        extra.insert("challenge".to_string(), Value::String(challenge.clone()));
+        if let Some(ref salt) = user.salt {
+            extra.insert("salt".to_string(), Value::String(salt.salt.clone()));
+            extra.insert(
+                "iterations".to_string(),
+                Value::UnsignedInteger(u64::from(salt.iterations)),
+            );
+            extra.insert(
+                "keylen".to_string(),
+                Value::UnsignedInteger(salt.keylen as u64),
+            );
+        }
+        Some(HelloOutcome::Challenge(
+            "wampcra".to_string(),
+            extra,
+            PendingAuth {
+                identity: Identity {
+                    authid: Some(authid.clone()),
+                    authrole: user.authrole.clone(),
+                    authmethod: "wampcra".to_string(),
+                    authprovider: "static".to_string(),
+                    authextra: None,
+                },
+                challenge: PendingChallenge::WampCra {
+                    challenge: challenge,
+                    secret: user.secret.clone(),
+                },
+            },
+        ))
+    }
+
+    fn challenge_cryptosign(&self, details: &HelloDetails) -> Option<HelloOutcome> {
+        let public_key = match details
+            .authextra
+            .as_ref()
+            .and_then(|authextra| authextra.get("pubkey"))
+        {
+            Some(&Value::String(ref public_key)) => public_key.to_lowercase(),
+            _ => return None,
+        };
+        let user = match self.cryptosign.get(&public_key) {
+            Some(user) => user,
+            None => return None,
+        };
+        if let Some(ref authid) = details.authid {
+            if *authid != user.authid {
+                return None;
+            }
+        }
+        let challenge = cryptosign::challenge();
+        let mut extra = HashMap::new();
+        extra.insert("challenge".to_string(), Value::String(challenge.clone()));
+        Some(HelloOutcome::Challenge(
+            "cryptosign".to_string(),
+            extra,
+            PendingAuth {
+                identity: Identity {
+                    authid: Some(user.authid.clone()),
+                    authrole: user.authrole.clone(),
+                    authmethod: "cryptosign".to_string(),
+                    authprovider: "static".to_string(),
+                    authextra: None,
+                },
+                challenge: PendingChallenge::Cryptosign {
+                    challenge: challenge,
+                    public_key: public_key,
+                },
+            },
+        ))
+    }
+
+    fn challenge_scram(&self, details: &HelloDetails) -> Option<HelloOutcome> {
+        let authid = match details.authid {
+            Some(ref authid) => authid,
+            None => return None,
+        };
+        let client_nonce = match details
+            .authextra
+            .as_ref()
+            .and_then(|authextra| authextra.get("nonce"))
+        {
+            Some(&Value::String(ref nonce)) => nonce,
+            _ => return None,
+        };
+        let user = match self.scram.get(authid) {
+            Some(user) => user,
+            None => return None,
+        };
+        let nonce = format!("{}{}", client_nonce, auth::nonce());
+        let auth_message = scram::auth_message(
+            authid,
+            client_nonce,
+            &nonce,
+            &user.keys.salt,
+            user.keys.iterations,
+        );
+
+        let mut extra = HashMap::new();
+        extra.insert("nonce".to_string(), Value::String(nonce));
+        extra.insert("salt".to_string(), Value::String(user.keys.salt.clone()));
+        extra.insert(
+            "kdf".to_string(),
+            Value::String(scram::KDF_PBKDF2.to_string()),
+        );
+        extra.insert(
+            "iterations".to_string(),
+            Value::UnsignedInteger(u64::from(user.keys.iterations)),
+        );
+        Some(HelloOutcome::Challenge(
+            "wamp-scram".to_string(),
+            extra,
+            PendingAuth {
+                identity: Identity {
+                    authid: Some(authid.clone()),
+                    authrole: user.authrole.clone(),
+                    authmethod: "wamp-scram".to_string(),
+                    authprovider: "static".to_string(),
+                    authextra: None,
+                },
+                challenge: PendingChallenge::Scram {
+                    auth_message: auth_message,
+                    keys: user.keys.clone(),
+                },
+            },
+        ))
+    }
+
+    fn challenge_ticket(&self, details: &HelloDetails) -> Option<HelloOutcome> {
+        let authid = match details.authid {
+            Some(ref authid) => authid,
+            None => return None,
+        };
+        let validator = match self.ticket_validator {
+            Some(ref validator) => Arc::clone(validator),
+            None => return None,
+        };
+        Some(HelloOutcome::Challenge(
+            "ticket".to_string(),
+            HashMap::new(),
+            PendingAuth {
+                identity: Identity {
+                    authid: Some(authid.clone()),
+                    authrole: String::new(),
+                    authmethod: "ticket".to_string(),
+                    authprovider: "dynamic".to_string(),
+                    authextra: None,
+                },
+                challenge: PendingChallenge::Ticket {
+                    validator: validator,
+                },
+            },
+        ))
+    }
+}
+
+impl PendingAuth {
+    /// Checks the signature a session sent in response to its challenge, returning the identity
+    /// the session should be given if it is valid.
+    pub fn verify(self, signature: &str, _extra: &Dict) -> Option<Identity> {
+        let mut identity = self.identity;
+        match self.challenge {
+            PendingChallenge::WampCra {
+                ref challenge,
+                ref secret,
+            } => {
+                if !wampcra::verify(secret, challenge, signature) {
+                    return None;
+                }
+            }
+            PendingChallenge::Ticket { ref validator } => {
+                let authrole = {
+                    let authid = identity.authid.as_ref().map_or("", |authid| &authid[..]);
+                    validator.validate(authid, signature)
+                };
+                match authrole {
+                    Some(authrole) => identity.authrole = authrole,
+                    None => return None,
+                }
+            }
+            PendingChallenge::Cryptosign {
+                ref challenge,
+                ref public_key,
+            } => {
+                if !cryptosign::verify(public_key, challenge, signature) {
+                    return None;
+                }
+            }
+            PendingChallenge::Scram {
+                ref auth_message,
+                ref keys,
+            } => {
+                if !keys.verify(auth_message, signature) {
+                    return None;
+                }
+                let mut authextra = HashMap::new();
+                authextra.insert(
+                    "scram_server_signature".to_string(),
+                    Value::String(keys.server_signature(auth_message)),
+                );
+                identity.authextra = Some(authextra);
+            }
+        }
+        Some(identity)
+    }
+}
+
+impl PendingAuthentication {
+    pub fn new(
+        session: Arc<Mutex<ConnectionInfo>>,
+        authenticator: Arc<Mutex<ConnectionInfo>>,
+        authid: Option<String>,
+        authmethod: String,
+    ) -> PendingAuthentication {
+        PendingAuthentication {
+            session: session,
+            authenticator: authenticator,
+            authid: authid,
+            authmethod: authmethod,
+            deadline: Instant::now() + Duration::from_millis(AUTHENTICATION_TIMEOUT),
+        }
+    }
+
+    /// Whether the authenticator has run out of time to answer.
+    pub fn is_expired(&self) -> bool {
+        self.deadline <= Instant::now()
+    }
+
+    /// Accepts the session with the identity the authenticator returned.  The authenticator may
+    /// return either the role to give the session, or a dictionary with an `authrole` and,
+    /// optionally, an `authid` and `extra` data for the WELCOME message.
+    pub fn accept(self, args: &Option<List>) {
+        let identity = match args.as_ref().and_then(|args| args.first()) {
+            Some(&Value::String(ref authrole)) => Some(Identity {
+                authid: self.authid.clone(),
+                authrole: authrole.clone(),
+                authmethod: self.authmethod.clone(),
+                authprovider: "dynamic".to_string(),
+                authextra: None,
+            }),
+            Some(&Value::Dict(ref result)) => match result.get("authrole") {
+                Some(&Value::String(ref authrole)) => Some(Identity {
+                    authid: match result.get("authid") {
+                        Some(&Value::String(ref authid)) => Some(authid.clone()),
+                        _ => self.authid.clone(),
+                    },
+                    authrole: authrole.clone(),
+                    authmethod: self.authmethod.clone(),
+                    authprovider: "dynamic".to_string(),
+                    authextra: match result.get("extra") {
+                        Some(&Value::Dict(ref extra)) => Some(extra.clone()),
+                        _ => None,
+                    },
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+        match identity {
+            Some(identity) => self.resolve(Ok(identity)),
+            None => {
+                warn!("Authenticator returned a result without an authrole");
+                self.reject()
+            }
+        }
+    }
+
+    /// Rejects the session.
+    pub fn reject(self) {
+        self.resolve(Err(Reason::AuthenticationFailed));
+    }
+
+    fn resolve(&self, outcome: Result<Identity, Reason>) {
+        let mut session = self.session.lock().unwrap();
+        session.authentication = Some(outcome);
+        session.sender.timeout(0, RESUME_AUTHENTICATION).ok();
+    }
+}
+
+/// Forgets the authentications a session that is leaving was waiting for or was asked to decide.
+/// Sessions that were waiting for the leaving authenticator are refused.
+pub fn forget_pending_authentications(
+    pending: &mut HashMap<ID, PendingAuthentication>,
+    session: &Arc<Mutex<ConnectionInfo>>,
+) {
+    pending.retain(|_, pending| {
+        if Arc::ptr_eq(&pending.session, session) {
+            false
+        } else if Arc::ptr_eq(&pending.authenticator, session) {
+            pending.resolve(Err(Reason::AuthenticationFailed));
+            false
+        } else {
+            true
+        }
+    });
+}
@@ -0,0 +1,462 @@
+//! Contains the roles a realm uses to decide which actions a session may perform on which URIs.
+use super::{random_id, ConnectionHandler, ConnectionInfo};
+use messages::{ErrorType, InvocationDetails, Message, Reason, URI};
+use router::messaging::send_message;
+use router::meta::session_details;
+use router::pubsub::{PatternData, SubscriptionPatternNode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ws::util::Token;
+use {Error, ErrorKind, List, MatchingPolicy, Value, WampResult, ID};
+
+/// The timeout token used to wake a session up once its deferred messages have been authorized.
+pub const RESUME_DEFERRED: Token = Token(1);
+
+/// How long, in milliseconds, the authorizer has to decide before the action is denied.
+const AUTHORIZATION_TIMEOUT: u64 = 10000;
+
+/// An action that a session may be allowed to perform on a URI.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Publish,
+    Subscribe,
+    Call,
+    Register,
+}
+
+/// The actions a permission rule allows.  Any action that is not allowed is denied.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Permissions {
+    pub publish: bool,
+    pub subscribe: bool,
+    pub call: bool,
+    pub register: bool,
+}
+
+/// The permission rules of each role in a realm.
+///
+/// The rules of each role are kept in a pattern trie, so that the rules matching a URI can be
+/// found the same way as the subscriptions matching a topic.  If several rules match, exact
+/// rules take precedence over prefix rules, which take precedence over wildcard rules.  Longer
+/// patterns take precedence over shorter ones with the same matching policy.
+pub struct RoleStore {
+    roles: HashMap<String, SubscriptionPatternNode<Rule>>,
+}
+
+/// A call to the realm's authorizer that has not been answered yet.
+pub struct PendingAuthorization {
+    session: Arc<Mutex<ConnectionInfo>>,
+    authorizer: Arc<Mutex<ConnectionInfo>>,
+    uri: String,
+    action: Action,
+    deadline: Instant,
+}
+
+struct Rule {
+    id: ID,
+    pattern_len: usize,
+    permissions: Permissions,
+}
+
+impl PatternData for Rule {
+    fn get_id(&self) -> ID {
+        self.id
+    }
+}
+
+impl Action {
+    /// The name of the action, as it is passed to the authorizer.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Publish => "publish",
+            Action::Subscribe => "subscribe",
+            Action::Call => "call",
+            Action::Register => "register",
+        }
+    }
+}
+
+impl Permissions {
+    /// Permissions that allow every action.
+    pub fn all() -> Permissions {
+        Permissions {
+            publish: true,
+            subscribe: true,
+            call: true,
+            register: true,
+        }
+    }
+
+    /// Permissions that deny every action.
+    pub fn none() -> Permissions {
+        Permissions::default()
+    }
+
+    pub fn allows(&self, action: Action) -> bool {
+        match action {
+            Action::Publish => self.publish,
+            Action::Subscribe => self.subscribe,
+            Action::Call => self.call,
+            Action::Register => self.register,
+        }
+    }
+}
+
+impl RoleStore {
+    pub fn new() -> RoleStore {
+        RoleStore {
+            roles: HashMap::new(),
+        }
+    }
+
+    /// Whether any rules have been added.  A realm without rules allows every action.
+    pub fn is_enabled(&self) -> bool {
+        !self.roles.is_empty()
+    }
+
+    /// Adds a rule to a role, replacing any rule the role already has for the same pattern.
+    pub fn add_permission(
+        &mut self,
+        authrole: &str,
+        uri: &URI,
+        policy: MatchingPolicy,
+        permissions: Permissions,
+    ) -> Result<(), Reason> {
+        let rules = self.roles
+            .entry(authrole.to_string())
+            .or_insert_with(SubscriptionPatternNode::new);
+        let is_prefix = policy == MatchingPolicy::Prefix;
+        let replaced = rules
+            .get_subscription(&uri.uri, is_prefix)
+            .map(|(_, _, existing)| existing.iter().map(|rule| rule.id).collect::<Vec<_>>())
+            .unwrap_or_default();
+        for id in replaced {
+            let rule = Rule {
+                id: id,
+                pattern_len: 0,
+                permissions: Permissions::none(),
+            };
+            rules.unsubscribe_with(&uri.uri, &rule, is_prefix).ok();
+        }
+        let rule = Rule {
+            id: random_id(),
+            pattern_len: uri.uri.len(),
+            permissions: permissions,
+        };
+        rules
+            .subscribe_with(uri, rule, policy)
+            .map(|_| ())
+            .map_err(|e| e.reason())
+    }
+
+    /// Decides whether a session with the given role may perform an action on a URI.
+    pub fn is_authorized(&self, authrole: Option<&str>, uri: &URI, action: Action) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let rules = match authrole.and_then(|authrole| self.roles.get(authrole)) {
+            Some(rules) => rules,
+            None => return false,
+        };
+        rules
+            .filter(uri.clone())
+            .max_by_key(|&(rule, _, policy)| (precedence(policy), rule.pattern_len))
+            .map_or(false, |(rule, _, _)| rule.permissions.allows(action))
+    }
+}
+
+fn precedence(policy: MatchingPolicy) -> u8 {
+    match policy {
+        MatchingPolicy::Strict => 2,
+        MatchingPolicy::Prefix => 1,
+        MatchingPolicy::Wildcard => 0,
+    }
+}
+
+impl PendingAuthorization {
+    fn is_for(&self, session: &Arc<Mutex<ConnectionInfo>>, uri: &URI, action: Action) -> bool {
+        Arc::ptr_eq(&self.session, session) && self.uri == uri.uri && self.action == action
+    }
+
+    /// Records the authorizer's decision with the session, and wakes the session up so that it
+    /// can process the messages that were waiting for the decision.
+    pub fn resolve(self, allowed: bool) {
+        debug!(
+            "Authorizer {} {:?} {}",
+            if allowed { "allowed" } else { "denied" },
+            self.action,
+            self.uri
+        );
+        let mut session = self.session.lock().unwrap();
+        session
+            .authorizations
+            .insert((self.uri, self.action), allowed);
+        session.sender.timeout(0, RESUME_DEFERRED).ok();
+    }
+}
+
+/// Forgets the decisions a session that is leaving was waiting for or was asked to make.  Sessions
+/// that were waiting for the leaving session's decision are woken up to ask again.
+pub fn forget_pending_authorizations(
+    pending: &mut HashMap<ID, PendingAuthorization>,
+    session: &Arc<Mutex<ConnectionInfo>>,
+) {
+    pending.retain(|_, pending| {
+        if Arc::ptr_eq(&pending.session, session) {
+            false
+        } else if Arc::ptr_eq(&pending.authorizer, session) {
+            let waiting = pending.session.lock().unwrap();
+            waiting.sender.timeout(0, RESUME_DEFERRED).ok();
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Whether a message requests an action that the session must be authorized for.
+pub fn needs_authorization(message: &Message) -> bool {
+    match *message {
+        Message::Subscribe(..)
+        | Message::Publish(..)
+        | Message::Register(..)
+        | Message::Call(..) => true,
+        _ => false,
+    }
+}
+
+/// Interprets the result of a call to the authorizer.  The authorizer may return either a boolean,
+/// or a dictionary with an `allow` key.
+pub fn is_allowed(args: &Option<List>) -> bool {
+    match args.as_ref().and_then(|args| args.first()) {
+        Some(&Value::Boolean(allowed)) => allowed,
+        Some(&Value::Dict(ref decision)) => match decision.get("allow") {
+            Some(&Value::Boolean(allowed)) => allowed,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl ConnectionHandler {
+    /// Checks that this session may perform the action a message requests.  Returns false if the
+    /// decision has been delegated to the realm's authorizer, and the message must wait for it.
+    pub fn authorize_message(&self, message: &Message) -> WampResult<bool> {
+        match *message {
+            Message::Subscribe(request_id, _, ref topic) => {
+                self.authorize(topic, Action::Subscribe, ErrorType::Subscribe, request_id)
+            }
+            Message::Publish(request_id, _, ref topic, _, _) => {
+                self.authorize(topic, Action::Publish, ErrorType::Publish, request_id)
+            }
+            Message::Register(request_id, _, ref procedure) => {
+                self.authorize(procedure, Action::Register, ErrorType::Register, request_id)
+            }
+            Message::Call(request_id, _, ref procedure, _, _) => {
+                self.authorize(procedure, Action::Call, ErrorType::Call, request_id)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Checks that this session's role allows it to perform an action on a URI.
+    ///
+    /// If the realm has an authorizer, it is called the first time the session attempts each
+    /// action on each URI, and its decision is cached for the rest of the session.  Returns false
+    /// while the authorizer's decision is pending.
+    pub fn authorize(
+        &self,
+        uri: &URI,
+        action: Action,
+        error_type: ErrorType,
+        request_id: ID,
+    ) -> WampResult<bool> {
+        let realm = match self.realm {
+            Some(ref realm) => realm,
+            None => return Ok(true),
+        };
+        let (authrole, cached) = {
+            let info = self.info.lock().unwrap();
+            (
+                info.authrole.clone(),
+                info.authorizations.get(&(uri.uri.clone(), action)).cloned(),
+            )
+        };
+        let not_authorized = || {
+            debug!(
+                "Session with role {:?} is not authorized to {:?} {}",
+                authrole, action, uri.uri
+            );
+            Err(Error::new(ErrorKind::ErrorReason(
+                error_type,
+                request_id,
+                Reason::NotAuthorized,
+            )))
+        };
+        let mut realm = realm.lock().unwrap();
+        if !realm.roles.is_authorized(
+            authrole.as_ref().map(|authrole| &authrole[..]),
+            uri,
+            action,
+        ) {
+            return not_authorized();
+        }
+        let authorizer = match realm.authorizer {
+            Some(ref authorizer) => authorizer.clone(),
+            None => return Ok(true),
+        };
+        if action == Action::Register && uri.uri == authorizer.uri {
+            // The authorizer can not be asked whether it may register itself
+            return Ok(true);
+        }
+        match cached {
+            Some(true) => return Ok(true),
+            Some(false) => return not_authorized(),
+            None => {}
+        }
+
+        let manager = &mut realm.registration_manager;
+        let pending = manager
+            .pending_authorizations
+            .iter()
+            .find(|&(_, pending)| pending.is_for(&self.info, uri, action))
+            .map(|(&invocation_id, pending)| (invocation_id, pending.deadline));
+        match pending {
+            Some((invocation_id, deadline)) if deadline <= Instant::now() => {
+                warn!("Authorizer did not decide in time whether to allow {}", uri.uri);
+                manager.pending_authorizations.remove(&invocation_id);
+                return not_authorized();
+            }
+            Some(_) => return Ok(false),
+            None => {}
+        }
+        let (authorizer_session, procedure_id) =
+            match manager.registrations.get_registrant_for(authorizer.clone()) {
+                Ok((registrant, procedure_id, _)) => (Arc::clone(registrant), procedure_id),
+                Err(_) => {
+                    warn!("Authorizer {} is not registered", authorizer.uri);
+                    return not_authorized();
+                }
+            };
+        let invocation_id = random_id();
+        let args = vec![
+            Value::Dict(session_details(&self.info.lock().unwrap())),
+            Value::String(uri.uri.clone()),
+            Value::String(action.name().to_string()),
+        ];
+        manager.pending_authorizations.insert(
+            invocation_id,
+            PendingAuthorization {
+                session: Arc::clone(&self.info),
+                authorizer: Arc::clone(&authorizer_session),
+                uri: uri.uri.clone(),
+                action: action,
+                deadline: Instant::now() + Duration::from_millis(AUTHORIZATION_TIMEOUT),
+            },
+        );
+        // Wakes the session up to deny the action if the authorizer never answers
+        try!(
+            self.info
+                .lock()
+                .unwrap()
+                .sender
+                .timeout(AUTHORIZATION_TIMEOUT, RESUME_DEFERRED)
+                .map_err(|e| Error::new(ErrorKind::WSError(e)))
+        );
+        try!(send_message(
+            &authorizer_session,
+            &Message::Invocation(
+                invocation_id,
+                procedure_id,
+                InvocationDetails::new(),
+                Some(args),
+                None,
+            )
+        ));
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_allowed, Action, Permissions, RoleStore};
+    use std::collections::HashMap;
+    use {MatchingPolicy, Value, URI};
+
+    #[test]
+    fn checking_permissions() {
+        let mut roles = RoleStore::new();
+        assert!(roles.is_authorized(None, &URI::new("com.example.topic"), Action::Publish));
+
+        let subscribe_only = Permissions {
+            subscribe: true,
+            ..Permissions::none()
+        };
+        roles
+            .add_permission(
+                "user",
+                &URI::new("com.example"),
+                MatchingPolicy::Prefix,
+                subscribe_only,
+            )
+            .unwrap();
+        roles
+            .add_permission(
+                "user",
+                &URI::new("com.example.user"),
+                MatchingPolicy::Prefix,
+                Permissions::all(),
+            )
+            .unwrap();
+        roles
+            .add_permission(
+                "user",
+                &URI::new("com.example.user.admin"),
+                MatchingPolicy::Strict,
+                Permissions::none(),
+            )
+            .unwrap();
+        roles
+            .add_permission(
+                "user",
+                &URI::new("com..public"),
+                MatchingPolicy::Wildcard,
+                Permissions::all(),
+            )
+            .unwrap();
+
+        let user = Some("user");
+        assert!(roles.is_authorized(user, &URI::new("com.example.topic"), Action::Subscribe));
+        assert!(!roles.is_authorized(user, &URI::new("com.example.topic"), Action::Publish));
+        assert!(roles.is_authorized(user, &URI::new("com.example.user.topic"), Action::Publish));
+        assert!(!roles.is_authorized(user, &URI::new("com.example.user.admin"), Action::Call));
+        assert!(!roles.is_authorized(user, &URI::new("com.example.public"), Action::Register));
+        assert!(roles.is_authorized(user, &URI::new("com.other.public"), Action::Register));
+        assert!(!roles.is_authorized(user, &URI::new("org.example.topic"), Action::Subscribe));
+        assert!(!roles.is_authorized(None, &URI::new("com.example.topic"), Action::Subscribe));
+        assert!(!roles.is_authorized(Some("guest"), &URI::new("com.example"), Action::Subscribe));
+
+        roles
+            .add_permission(
+                "user",
+                &URI::new("com.example"),
+                MatchingPolicy::Prefix,
+                Permissions::none(),
+            )
+            .unwrap();
+        assert!(!roles.is_authorized(user, &URI::new("com.example.topic"), Action::Subscribe));
+    }
+
+    #[test]
+    fn interpreting_decisions() {
+        assert!(is_allowed(&Some(vec![Value::Boolean(true)])));
+        assert!(!is_allowed(&Some(vec![Value::Boolean(false)])));
+        let mut decision = HashMap::new();
+        decision.insert("allow".to_string(), Value::Boolean(true));
+        assert!(is_allowed(&Some(vec![Value::Dict(decision)])));
+        assert!(!is_allowed(&Some(vec![Value::Dict(HashMap::new())])));
+        assert!(!is_allowed(&Some(vec![Value::String("yes".to_string())])));
+        assert!(!is_allowed(&None));
+    }
+}
@@ -1,27 +1,212 @@
-use super::{ConnectionHandler, ConnectionState, WAMP_JSON, WAMP_MSGPACK};
-use std::sync::Arc;
+use super::{random_id, ConnectionHandler, ConnectionState, Realm};
+use itertools::Itertools;
+use std::sync::{Arc, Mutex};
 
+use router::auth::{HelloOutcome, Identity, PendingAuthentication, AUTHENTICATION_TIMEOUT,
+                   RESUME_AUTHENTICATION};
 use router::messaging::send_message;
+use router::meta::{on_join, session_details};
+use serializer;
 use ws::{CloseCode, Error as WSError, ErrorKind as WSErrorKind, Request, Response,
          Result as WSResult};
 
-use messages::{ErrorDetails, HelloDetails, Message, Reason, RouterRoles, WelcomeDetails, URI};
-use {Error, ErrorKind, WampResult};
+use messages::{ErrorDetails, HelloDetails, InvocationDetails, Message, Reason, WelcomeDetails,
+               URI};
+use {Dict, Error, ErrorKind, Value, WampResult, ID};
 
 impl ConnectionHandler {
-    pub fn handle_hello(&mut self, realm: URI, _details: HelloDetails) -> WampResult<()> {
-        debug!("Responding to hello message (realm: {:?})", realm);
+    pub fn handle_hello(&mut self, realm_uri: URI, details: HelloDetails) -> WampResult<()> {
+        debug!("Responding to hello message (realm: {:?})", realm_uri);
+        let realm = match self.router.realms.lock().unwrap().get(&realm_uri.uri) {
+            Some(realm) => Arc::clone(realm),
+            None => return Err(Error::new(ErrorKind::HandshakeError(Reason::NoSuchRealm))),
+        };
         let id = {
             let mut info = self.info.lock().unwrap();
-            info.state = ConnectionState::Connected;
+            info.roles = details.roles().clone();
             info.id
         };
+        let (outcome, delegated) = {
+            let realm = realm.lock().unwrap();
+            (
+                realm.credentials.authenticate(id, &details),
+                realm.authenticator.is_some(),
+            )
+        };
+        match outcome {
+            HelloOutcome::Abort(_) if delegated => {
+                if details.authid.is_some() && details.authmethods.iter().any(|m| m == "ticket") {
+                    // The ticket is only sent in response to a challenge, so the authenticator
+                    // is asked once the session has answered it
+                    debug!("Challenging session {} with ticket for the authenticator", id);
+                    self.delegated_ticket = Some((realm, realm_uri, details));
+                    send_message(&self.info, &Message::Challenge("ticket".to_string(), Dict::new()))
+                } else {
+                    let authmethod = details
+                        .authmethods
+                        .first()
+                        .cloned()
+                        .unwrap_or_else(|| "anonymous".to_string());
+                    self.delegate_authentication(realm, realm_uri, &details, authmethod, None)
+                }
+            }
+            HelloOutcome::Welcome(identity) => self.join_realm(realm, identity),
+            HelloOutcome::Challenge(authmethod, extra, pending) => {
+                debug!("Challenging session {} with {}", id, authmethod);
+                self.pending_auth = Some((realm, pending));
+                send_message(&self.info, &Message::Challenge(authmethod, extra))
+            }
+            HelloOutcome::Abort(reason) => Err(Error::new(ErrorKind::HandshakeError(reason))),
+        }
+    }
+
+    pub fn handle_authenticate(&mut self, signature: String, extra: Dict) -> WampResult<()> {
+        debug!("Responding to authenticate message");
+        if let Some((realm, realm_uri, details)) = self.delegated_ticket.take() {
+            return self.delegate_authentication(
+                realm,
+                realm_uri,
+                &details,
+                "ticket".to_string(),
+                Some(signature),
+            );
+        }
+        match self.pending_auth.take() {
+            Some((realm, pending)) => match pending.verify(&signature, &extra) {
+                Some(identity) => self.join_realm(realm, identity),
+                None => Err(Error::new(ErrorKind::HandshakeError(
+                    Reason::AuthenticationFailed,
+                ))),
+            },
+            None => Err(Error::new(ErrorKind::UnexpectedMessage(
+                "Received an authenticate message without sending a challenge",
+            ))),
+        }
+    }
 
-        try!(self.set_realm(realm.uri));
-        send_message(
-            &self.info,
-            &Message::Welcome(id, WelcomeDetails::new(RouterRoles::new())),
-        )
+    /// Asks the realm's authenticator to decide who the session is.  The session joins the realm
+    /// once the authenticator answers, and is refused if it does not answer in time.
+    ///
+    /// Only sessions that authenticate with a ticket give the authenticator something to check,
+    /// in the `ticket` entry of the session details.  For every other method the authenticator
+    /// only sees the authid and authextra the session claimed in its HELLO.
+    fn delegate_authentication(
+        &mut self,
+        realm: Arc<Mutex<Realm>>,
+        realm_uri: URI,
+        details: &HelloDetails,
+        authmethod: String,
+        ticket: Option<String>,
+    ) -> WampResult<()> {
+        let invocation_id = random_id();
+        {
+            let mut realm = realm.lock().unwrap();
+            let authenticator = match realm.authenticator {
+                Some(ref authenticator) => authenticator.clone(),
+                None => {
+                    return Err(Error::new(ErrorKind::HandshakeError(
+                        Reason::AuthenticationFailed,
+                    )))
+                }
+            };
+            let manager = &mut realm.registration_manager;
+            let (registrant, procedure_id) =
+                match manager.registrations.get_registrant_for(authenticator.clone()) {
+                    Ok((registrant, procedure_id, _)) => (Arc::clone(registrant), procedure_id),
+                    Err(_) => {
+                        warn!("Authenticator {} is not registered", authenticator.uri);
+                        return Err(Error::new(ErrorKind::HandshakeError(
+                            Reason::AuthenticationFailed,
+                        )));
+                    }
+                };
+            let mut session = session_details(&self.info.lock().unwrap());
+            if let Some(ref authid) = details.authid {
+                session.insert("authid".to_string(), Value::String(authid.clone()));
+            }
+            session.insert(
+                "authmethods".to_string(),
+                Value::List(
+                    details
+                        .authmethods
+                        .iter()
+                        .map(|authmethod| Value::String(authmethod.clone()))
+                        .collect(),
+                ),
+            );
+            if let Some(ref authextra) = details.authextra {
+                session.insert("authextra".to_string(), Value::Dict(authextra.clone()));
+            }
+            if let Some(ticket) = ticket {
+                session.insert("ticket".to_string(), Value::String(ticket));
+            }
+            manager.pending_authentications.insert(
+                invocation_id,
+                PendingAuthentication::new(
+                    Arc::clone(&self.info),
+                    Arc::clone(&registrant),
+                    details.authid.clone(),
+                    authmethod,
+                ),
+            );
+            // Wakes the session up to refuse it if the authenticator never answers
+            try!(
+                self.info
+                    .lock()
+                    .unwrap()
+                    .sender
+                    .timeout(AUTHENTICATION_TIMEOUT, RESUME_AUTHENTICATION)
+                    .map_err(|e| Error::new(ErrorKind::WSError(e)))
+            );
+            debug!("Asking {} to authenticate session", authenticator.uri);
+            try!(send_message(
+                &registrant,
+                &Message::Invocation(
+                    invocation_id,
+                    procedure_id,
+                    InvocationDetails::new(),
+                    Some(vec![Value::String(realm_uri.uri), Value::Dict(session)]),
+                    None,
+                )
+            ));
+        }
+        self.authenticating = Some((realm, invocation_id));
+        Ok(())
+    }
+
+    /// Finishes the handshake once the authenticator has answered, or refuses the session once
+    /// the authenticator has run out of time.
+    pub fn resume_authentication(&mut self) -> WampResult<()> {
+        let outcome = self.info.lock().unwrap().authentication.take();
+        match (self.authenticating.take(), outcome) {
+            (Some((realm, _)), Some(Ok(identity))) => self.join_realm(realm, identity),
+            (Some(_), Some(Err(reason))) => Err(Error::new(ErrorKind::HandshakeError(reason))),
+            (Some((realm, invocation_id)), None) => {
+                if self.authentication_expired(&realm, invocation_id) {
+                    warn!("Authenticator did not answer in time");
+                    Err(Error::new(ErrorKind::HandshakeError(
+                        Reason::AuthenticationFailed,
+                    )))
+                } else {
+                    self.authenticating = Some((realm, invocation_id));
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks whether the authenticator ran out of time to answer, forgetting the call if so.
+    fn authentication_expired(&self, realm: &Arc<Mutex<Realm>>, invocation_id: ID) -> bool {
+        let mut realm = realm.lock().unwrap();
+        let pending = &mut realm.registration_manager.pending_authentications;
+        let expired = pending
+            .get(&invocation_id)
+            .map_or(true, |pending| pending.is_expired());
+        if expired {
+            pending.remove(&invocation_id);
+        }
+        expired
     }
 
     pub fn handle_goodbye(&mut self, _details: ErrorDetails, reason: Reason) -> WampResult<()> {
@@ -52,6 +237,7 @@ impl ConnectionHandler {
                     "Received goodbye message in response to our goodbye message with reason: {:?}",
                     reason
                 );
+                self.remove();
                 let mut info = self.info.lock().unwrap();
                 info.state = ConnectionState::Disconnected;
                 match info.sender.close(CloseCode::Normal) {
@@ -66,20 +252,35 @@ impl ConnectionHandler {
         }
     }
 
-    fn set_realm(&mut self, realm: String) -> WampResult<()> {
-        debug!("Setting realm to {}", realm);
-        if let Some(realm) = self.router.realms.lock().unwrap().get(&realm) {
-            {
-                realm
-                    .lock()
-                    .unwrap()
-                    .connections
-                    .push(Arc::clone(&self.info));
-            }
-            self.realm = Some(Arc::clone(realm));
-        } else {
-            return Err(Error::new(ErrorKind::HandshakeError(Reason::NoSuchRealm)));
-        }
+    fn join_realm(&mut self, realm: Arc<Mutex<Realm>>, identity: Identity) -> WampResult<()> {
+        let id = {
+            let mut info = self.info.lock().unwrap();
+            info.state = ConnectionState::Connected;
+            info.authid = identity.authid.clone();
+            info.authrole = Some(identity.authrole.clone());
+            info.id
+        };
+        debug!(
+            "Session {} joined as {:?} with role {}",
+            id, identity.authid, identity.authrole
+        );
+        realm
+            .lock()
+            .unwrap()
+            .connections
+            .push(Arc::clone(&self.info));
+        self.realm = Some(Arc::clone(&realm));
+
+        let roles = realm.lock().unwrap().router_roles();
+        let mut details = WelcomeDetails::new(roles);
+        details.authid = identity.authid;
+        details.authrole = Some(identity.authrole);
+        details.authmethod = Some(identity.authmethod);
+        details.authprovider = Some(identity.authprovider);
+        details.authextra = identity.authextra;
+        try!(send_message(&self.info, &Message::Welcome(id, details)));
+        let session = session_details(&self.info.lock().unwrap());
+        on_join(&realm.lock().unwrap(), session);
         Ok(())
     }
 
@@ -87,18 +288,21 @@ impl ConnectionHandler {
         debug!("Checking protocol");
         let protocols = try!(request.protocols());
         for protocol in protocols {
-            if protocol == WAMP_JSON || protocol == WAMP_MSGPACK {
+            if let Some(serializer) = serializer::from_protocol(protocol) {
                 response.set_protocol(protocol);
                 let mut info = self.info.lock().unwrap();
-                info.protocol = protocol.to_string();
+                info.serializer = serializer;
                 return Ok(());
             }
         }
         Err(WSError::new(
             WSErrorKind::Protocol,
             format!(
-                "Neither {} nor {} were selected as Websocket sub-protocols",
-                WAMP_JSON, WAMP_MSGPACK
+                "None of {} were selected as Websocket sub-protocols",
+                serializer::all()
+                    .iter()
+                    .map(|serializer| serializer.protocol())
+                    .join(", ")
             ),
         ))
     }
@@ -1,52 +1,58 @@
-use super::{ConnectionHandler, ConnectionInfo, ConnectionState, WAMP_JSON};
+use super::{ConnectionHandler, ConnectionInfo, ConnectionState};
 use std::sync::{Arc, Mutex};
 use ws::{CloseCode, Error as WSError, ErrorKind as WSErrorKind, Handler, Message as WSMessage,
-         Request, Response, Result as WSResult, Sender};
+         Request, Response, Result as WSResult};
 
 use messages::{ErrorDetails, ErrorType, Message, Reason};
-use rmp_serde::Deserializer as RMPDeserializer;
-use rmp_serde::Serializer;
-use serde::{Deserialize, Serialize};
-use serde_json;
+use router::auth::RESUME_AUTHENTICATION;
+use router::authorization::{needs_authorization, RESUME_DEFERRED};
 use std::collections::HashMap;
-use std::io::Cursor;
-use utils::StructMapWriter;
+use ws::util::Token;
 use {Dict, Error, ErrorKind, List, WampResult, ID};
 
 pub fn send_message(info: &Arc<Mutex<ConnectionInfo>>, message: &Message) -> WampResult<()> {
     let info = info.lock().unwrap();
 
-    debug!("Sending message {:?} via {}", message, info.protocol);
-    let send_result = if info.protocol == WAMP_JSON {
-        send_message_json(&info.sender, message)
-    } else {
-        send_message_msgpack(&info.sender, message)
-    };
-    match send_result {
+    debug!("Sending message {:?} via {}", message, info.serializer.protocol());
+    match info.sender.send(info.serializer.serialize(message)) {
         Ok(()) => Ok(()),
         Err(e) => Err(Error::new(ErrorKind::WSError(e))),
     }
 }
 
-fn send_message_json(sender: &Sender, message: &Message) -> WSResult<()> {
-    // Send the message
-    sender.send(WSMessage::Text(serde_json::to_string(message).unwrap()))
-}
-
-fn send_message_msgpack(sender: &Sender, message: &Message) -> WSResult<()> {
-    // Send the message
-    let mut buf: Vec<u8> = Vec::new();
-    message
-        .serialize(&mut Serializer::with(&mut buf, StructMapWriter))
-        .unwrap();
-    sender.send(WSMessage::Binary(buf))
-}
-
 impl ConnectionHandler {
     fn handle_message(&mut self, message: Message) -> WampResult<()> {
         debug!("Received message {:?}", message);
+        if let Message::Goodbye(..) = message {
+            self.deferred.clear();
+        } else if let Message::Cancel(request_id, _) = message {
+            if self.cancel_deferred_call(request_id) {
+                return Err(Error::new(ErrorKind::ErrorReason(
+                    ErrorType::Call,
+                    request_id,
+                    Reason::Cancelled,
+                )));
+            }
+        } else if !self.deferred.is_empty() && needs_authorization(&message) {
+            // Keep requests in order while earlier ones wait for the authorizer.  Other messages,
+            // such as the results of invocations, are not held back.
+            self.deferred.push_back(message);
+            return Ok(());
+        }
+        if try!(self.authorize_message(&message)) {
+            self.dispatch_message(message)
+        } else {
+            debug!("Deferring message until the authorizer has decided");
+            self.deferred.push_back(message);
+            Ok(())
+        }
+    }
+
+    fn dispatch_message(&mut self, message: Message) -> WampResult<()> {
+        try!(self.check_features(&message));
         match message {
             Message::Hello(realm, details) => self.handle_hello(realm, details),
+            Message::Authenticate(signature, extra) => self.handle_authenticate(signature, extra),
             Message::Subscribe(request_id, options, topic) => {
                 self.handle_subscribe(request_id, options, topic)
             }
@@ -66,6 +72,7 @@ impl ConnectionHandler {
             Message::Call(request_id, options, procedure, args, kwargs) => {
                 self.handle_call(request_id, options, procedure, args, kwargs)
             }
+            Message::Cancel(request_id, options) => self.handle_cancel(request_id, options),
             Message::Yield(invocation_id, options, args, kwargs) => {
                 self.handle_yield(invocation_id, options, args, kwargs)
             }
@@ -76,6 +83,41 @@ impl ConnectionHandler {
         }
     }
 
+    /// Drops a call that is still waiting for the authorizer.  Returns false if there is no such
+    /// call.
+    fn cancel_deferred_call(&mut self, request_id: ID) -> bool {
+        let position = self.deferred.iter().position(|message| match *message {
+            Message::Call(call_id, ..) => call_id == request_id,
+            _ => false,
+        });
+        match position {
+            Some(position) => {
+                self.deferred.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Processes the messages that were waiting for the authorizer, stopping at the first one
+    /// that is still waiting.
+    fn resume_deferred(&mut self) -> WSResult<()> {
+        while let Some(message) = self.deferred.pop_front() {
+            let result = match self.authorize_message(&message) {
+                Ok(true) => self.dispatch_message(message),
+                Ok(false) => {
+                    self.deferred.push_front(message);
+                    break;
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                try!(self.on_message_error(e));
+            }
+        }
+        Ok(())
+    }
+
     fn handle_error(
         &mut self,
         e_type: ErrorType,
@@ -94,14 +136,33 @@ impl ConnectionHandler {
                 Some(ref realm) => {
                     let mut realm = realm.lock().unwrap();
                     let manager = &mut realm.registration_manager;
-                    if let Some((call_id, callee)) = manager.active_calls.remove(&request_id) {
-                        let error_message =
-                            Message::Error(ErrorType::Call, call_id, details, reason, args, kwargs);
-                        send_message(&callee, &error_message)
+                    if let Some(pending) = manager.pending_authorizations.remove(&request_id) {
+                        warn!("Authorizer failed with {:?}", reason);
+                        pending.resolve(false);
+                        Ok(())
+                    } else if let Some(pending) =
+                        manager.pending_authentications.remove(&request_id)
+                    {
+                        debug!("Authenticator rejected session with {:?}", reason);
+                        pending.reject();
+                        Ok(())
+                    } else if let Some(call) = manager.active_calls.remove(&request_id) {
+                        let error_message = Message::Error(
+                            ErrorType::Call,
+                            call.call_id,
+                            details,
+                            reason,
+                            args,
+                            kwargs,
+                        );
+                        send_message(&call.caller, &error_message)
                     } else {
-                        Err(Error::new(ErrorKind::InvalidState(
-                            "Received an error message for a call that wasn't sent",
-                        )))
+                        // The call may have been cancelled while the callee was working on it
+                        debug!(
+                            "Discarding error for a call that is no longer active (id: {})",
+                            request_id
+                        );
+                        Ok(())
                     }
                 }
                 None => Err(Error::new(ErrorKind::InvalidState(
@@ -116,19 +177,8 @@ impl ConnectionHandler {
     }
 
     fn parse_message(&self, msg: WSMessage) -> WampResult<Message> {
-        match msg {
-            WSMessage::Text(payload) => match serde_json::from_str(&payload) {
-                Ok(message) => Ok(message),
-                Err(e) => Err(Error::new(ErrorKind::JSONError(e))),
-            },
-            WSMessage::Binary(payload) => {
-                let mut de = RMPDeserializer::new(Cursor::new(payload));
-                match Deserialize::deserialize(&mut de) {
-                    Ok(message) => Ok(message),
-                    Err(e) => Err(Error::new(ErrorKind::MsgPackError(e))),
-                }
-            }
-        }
+        let serializer = self.info.lock().unwrap().serializer;
+        serializer.deserialize(msg)
     }
 
     fn send_error(&self, err_type: ErrorType, request_id: ID, reason: Reason) -> WSResult<()> {
@@ -183,6 +233,10 @@ impl ConnectionHandler {
                 error!("Could not parse MsgPack: {}", e.description());
                 self.terminate_connection()
             }
+            ErrorKind::CBORError(e) => {
+                error!("Could not parse CBOR: {}", e);
+                self.terminate_connection()
+            }
             ErrorKind::MalformedData => unimplemented!(),
             ErrorKind::InvalidMessageType(msg) => {
                 error!("Router unable to handle message {:?}", msg);
@@ -228,6 +282,19 @@ impl Handler for ConnectionHandler {
         }
     }
 
+    fn on_timeout(&mut self, token: Token) -> WSResult<()> {
+        if token == RESUME_DEFERRED {
+            self.resume_deferred()
+        } else if token == RESUME_AUTHENTICATION {
+            match self.resume_authentication() {
+                Err(e) => self.on_message_error(e),
+                _ => Ok(()),
+            }
+        } else {
+            Ok(())
+        }
+    }
+
     fn on_close(&mut self, _code: CloseCode, _reason: &str) {
         let state = self.info.lock().unwrap().state.clone();
         if state != ConnectionState::Disconnected {
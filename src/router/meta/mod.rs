@@ -0,0 +1,158 @@
+mod registration;
+mod session;
+mod subscription;
+
+pub use self::registration::{is_registered, on_register, on_unregister};
+pub use self::session::{on_join, on_leave, session_details};
+pub use self::subscription::{is_subscribed, on_subscribe, on_unsubscribe};
+
+use super::{random_id, ConnectionHandler, Realm};
+use messages::{ErrorType, EventDetails, Message, Reason, ResultDetails, URI};
+use router::messaging::send_message;
+use std::collections::HashMap;
+use {ArgList, CallError, CallResult, Dict, Error, ErrorKind, List, MatchingPolicy, Value,
+     WampResult, ID};
+
+/// The result of a meta procedure, as the positional and keyword arguments of the RESULT message.
+type MetaResult = CallResult<(Option<List>, Option<Dict>)>;
+
+/// Meta procedures are implemented by the router itself, under the reserved `wamp.` namespace.
+pub fn is_meta_procedure(procedure: &URI) -> bool {
+    procedure.uri.starts_with("wamp.")
+}
+
+impl ConnectionHandler {
+    pub fn handle_meta_call(
+        &mut self,
+        request_id: ID,
+        procedure: URI,
+        args: Option<List>,
+        kwargs: Option<Dict>,
+    ) -> WampResult<()> {
+        debug!(
+            "Responding to meta procedure call (id: {}, procedure: {})",
+            request_id, procedure.uri
+        );
+        let args = args.unwrap_or_default();
+        let kwargs = kwargs.unwrap_or_default();
+        let result = match self.realm {
+            Some(ref realm) => {
+                let realm = realm.lock().unwrap();
+                let my_id = self.info.lock().unwrap().id;
+                match &procedure.uri[..] {
+                    "wamp.session.count" => session::count(&realm, &args),
+                    "wamp.session.list" => session::list(&realm, &args),
+                    "wamp.session.get" => session::get(&realm, &args),
+                    "wamp.session.kill" => session::kill(&realm, my_id, &args, &kwargs),
+                    "wamp.session.add_testament" => {
+                        session::add_testament(&mut self.testaments, &args, &kwargs)
+                    }
+                    "wamp.session.flush_testaments" => {
+                        session::flush_testaments(&mut self.testaments, &kwargs)
+                    }
+                    "wamp.registration.list" => registration::list(&realm),
+                    "wamp.registration.lookup" => registration::lookup(&realm, &args),
+                    "wamp.registration.match" => registration::match_procedure(&realm, &args),
+                    "wamp.registration.get" => registration::get(&realm, &args),
+                    "wamp.registration.list_callees" => registration::list_callees(&realm, &args),
+                    "wamp.registration.count_callees" => {
+                        registration::count_callees(&realm, &args)
+                    }
+                    "wamp.subscription.list" => subscription::list(&realm),
+                    "wamp.subscription.lookup" => subscription::lookup(&realm, &args),
+                    "wamp.subscription.match" => subscription::match_topic(&realm, &args),
+                    "wamp.subscription.get" => subscription::get(&realm, &args),
+                    "wamp.subscription.list_subscribers" => {
+                        subscription::list_subscribers(&realm, &args)
+                    }
+                    "wamp.subscription.count_subscribers" => {
+                        subscription::count_subscribers(&realm, &args)
+                    }
+                    "wamp.subscription.get_events" => subscription::get_events(&realm, &args),
+                    _ => Err(CallError::new(Reason::NoSuchProcedure, None, None)),
+                }
+            }
+            None => {
+                return Err(Error::new(ErrorKind::InvalidState(
+                    "Received a message while not attached to a realm",
+                )))
+            }
+        };
+        let result = match result {
+            Ok(result) if procedure.uri == "wamp.session.add_testament" => {
+                self.authorize_testament().map(|()| result)
+            }
+            result => result,
+        };
+        let message = match result {
+            Ok((args, kwargs)) => Message::Result(request_id, ResultDetails::new(), args, kwargs),
+            Err(e) => {
+                let (reason, args, kwargs) = e.into_tuple();
+                Message::Error(
+                    ErrorType::Call,
+                    request_id,
+                    HashMap::new(),
+                    reason,
+                    args,
+                    kwargs,
+                )
+            }
+        };
+        send_message(&self.info, &message)
+    }
+}
+
+/// Publishes a meta event to every session subscribed to the topic in the realm.
+fn publish_meta_event(realm: &Realm, topic: &str, args: List) {
+    let topic = URI::new(topic);
+    let publication_id = random_id();
+    let manager = &realm.subscription_manager;
+    for (subscriber, topic_id, policy) in manager.subscriptions.filter(topic.clone()) {
+        let details = if policy == MatchingPolicy::Strict {
+            EventDetails::new()
+        } else {
+            EventDetails::new_with_topic(topic.clone())
+        };
+        let event_message =
+            Message::Event(topic_id, publication_id, details, Some(args.clone()), None);
+        send_message(subscriber, &event_message).ok();
+    }
+}
+
+/// The name the meta API uses for a matching policy.
+fn match_policy_name(policy: MatchingPolicy) -> &'static str {
+    match policy {
+        MatchingPolicy::Strict => "exact",
+        MatchingPolicy::Prefix => "prefix",
+        MatchingPolicy::Wildcard => "wildcard",
+    }
+}
+
+/// Reads a required string from the positional arguments of a meta procedure call.
+fn require_string(args: &List, index: usize) -> CallResult<&str> {
+    try!(args.verify_len(index + 1));
+    Ok(try!(args.get_string(index)).unwrap_or_default())
+}
+
+/// Reads an ID from the positional arguments of a meta procedure call.
+fn get_id(args: &List, index: usize) -> CallResult<Option<ID>> {
+    match args.get(index) {
+        Some(&Value::UnsignedInteger(id)) => Ok(Some(id)),
+        Some(&Value::Integer(id)) if id >= 0 => Ok(Some(id as ID)),
+        Some(value) => Err(CallError::new(
+            Reason::InvalidArgument,
+            Some(vec![Value::String(format!(
+                "Expected id, got {}",
+                value.summarize()
+            ))]),
+            None,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Reads a required ID from the positional arguments of a meta procedure call.
+fn require_id(args: &List, index: usize) -> CallResult<ID> {
+    try!(args.verify_len(index + 1));
+    Ok(try!(get_id(args, index)).unwrap_or_default())
+}
@@ -0,0 +1,191 @@
+use super::{match_policy_name, publish_meta_event, require_id, require_string, MetaResult};
+use messages::Reason;
+use router::Realm;
+use std::collections::HashMap;
+use {ArgDict, CallError, Dict, InvocationPolicy, List, MatchingPolicy, Value, ID, URI};
+
+fn invocation_policy_name(policy: InvocationPolicy) -> &'static str {
+    match policy {
+        InvocationPolicy::Single => "single",
+        InvocationPolicy::RoundRobin => "roundrobin",
+        InvocationPolicy::Random => "random",
+        InvocationPolicy::First => "first",
+        InvocationPolicy::Last => "last",
+    }
+}
+
+/// Describes the registration with the given id, if it currently has any callees.
+fn registration_details(realm: &Realm, registration_id: ID) -> Option<Dict> {
+    let manager = &realm.registration_manager;
+    let &(ref uri, is_prefix) = match manager.registration_ids_to_uris.get(&registration_id) {
+        Some(entry) => entry,
+        None => return None,
+    };
+    manager
+        .registrations
+        .get_registration(uri, is_prefix)
+        .map(|(id, policy, invocation_policy, _)| {
+            let mut details = HashMap::new();
+            details.insert("id".to_string(), Value::UnsignedInteger(id));
+            details.insert("uri".to_string(), Value::String(uri.clone()));
+            details.insert(
+                "match".to_string(),
+                Value::String(match_policy_name(policy).to_string()),
+            );
+            details.insert(
+                "invoke".to_string(),
+                Value::String(invocation_policy_name(invocation_policy).to_string()),
+            );
+            details
+        })
+}
+
+/// Gets the session ids of the callees of the registration with the given id.
+fn callees(realm: &Realm, registration_id: ID) -> Option<Vec<ID>> {
+    let manager = &realm.registration_manager;
+    let &(ref uri, is_prefix) = match manager.registration_ids_to_uris.get(&registration_id) {
+        Some(entry) => entry,
+        None => return None,
+    };
+    manager
+        .registrations
+        .get_registration(uri, is_prefix)
+        .map(|(_, _, _, registrants)| {
+            registrants
+                .iter()
+                .map(|registrant| registrant.lock().unwrap().id)
+                .collect()
+        })
+}
+
+/// Publishes `wamp.registration.on_register`, preceded by `wamp.registration.on_create` if the
+/// session is the first callee of the registration.
+pub fn on_register(realm: &Realm, session_id: ID, registration_id: ID, created: bool) {
+    if created {
+        if let Some(details) = registration_details(realm, registration_id) {
+            publish_meta_event(
+                realm,
+                "wamp.registration.on_create",
+                vec![Value::UnsignedInteger(session_id), Value::Dict(details)],
+            );
+        }
+    }
+    publish_meta_event(
+        realm,
+        "wamp.registration.on_register",
+        vec![
+            Value::UnsignedInteger(session_id),
+            Value::UnsignedInteger(registration_id),
+        ],
+    );
+}
+
+/// Publishes `wamp.registration.on_unregister`, followed by `wamp.registration.on_delete` if the
+/// session was the last callee of the registration.
+pub fn on_unregister(realm: &Realm, session_id: ID, registration_id: ID) {
+    let args = vec![
+        Value::UnsignedInteger(session_id),
+        Value::UnsignedInteger(registration_id),
+    ];
+    publish_meta_event(realm, "wamp.registration.on_unregister", args.clone());
+    if callees(realm, registration_id).is_none() {
+        publish_meta_event(realm, "wamp.registration.on_delete", args);
+    }
+}
+
+/// Checks whether a registration with exactly the given pattern currently has callees.
+pub fn is_registered(realm: &Realm, procedure: &URI, policy: MatchingPolicy) -> bool {
+    realm
+        .registration_manager
+        .registrations
+        .get_registration(&procedure.uri, policy == MatchingPolicy::Prefix)
+        .is_some()
+}
+
+/// `wamp.registration.list()`
+pub fn list(realm: &Realm) -> MetaResult {
+    let mut exact = Vec::new();
+    let mut prefix = Vec::new();
+    let mut wildcard = Vec::new();
+    let manager = &realm.registration_manager;
+    for &(ref uri, is_prefix) in manager.registration_ids_to_uris.values() {
+        if let Some((id, policy, _, _)) = manager.registrations.get_registration(uri, is_prefix) {
+            match policy {
+                MatchingPolicy::Strict => exact.push(Value::UnsignedInteger(id)),
+                MatchingPolicy::Prefix => prefix.push(Value::UnsignedInteger(id)),
+                MatchingPolicy::Wildcard => wildcard.push(Value::UnsignedInteger(id)),
+            }
+        }
+    }
+    let mut registrations = HashMap::new();
+    registrations.insert("exact".to_string(), Value::List(exact));
+    registrations.insert("prefix".to_string(), Value::List(prefix));
+    registrations.insert("wildcard".to_string(), Value::List(wildcard));
+    Ok((Some(vec![Value::Dict(registrations)]), None))
+}
+
+/// `wamp.registration.lookup(procedure, options)`
+///
+/// Returns nothing if there is no registration with exactly the given pattern.
+pub fn lookup(realm: &Realm, args: &List) -> MetaResult {
+    let procedure = try!(require_string(args, 0));
+    let is_prefix = match args.get(1) {
+        Some(&Value::Dict(ref options)) => try!(options.get_string("match")) == Some("prefix"),
+        _ => false,
+    };
+    let registration = realm
+        .registration_manager
+        .registrations
+        .get_registration(procedure, is_prefix);
+    match registration {
+        Some((id, _, _, _)) => Ok((Some(vec![Value::UnsignedInteger(id)]), None)),
+        None => Ok((None, None)),
+    }
+}
+
+/// `wamp.registration.match(procedure)`
+///
+/// Returns nothing if a call to the procedure would not be routed to any registration.
+pub fn match_procedure(realm: &Realm, args: &List) -> MetaResult {
+    let procedure = try!(require_string(args, 0));
+    let registration = realm
+        .registration_manager
+        .registrations
+        .match_registration(&URI::new(procedure));
+    match registration {
+        Some((id, _)) => Ok((Some(vec![Value::UnsignedInteger(id)]), None)),
+        None => Ok((None, None)),
+    }
+}
+
+/// `wamp.registration.get(registration_id)`
+pub fn get(realm: &Realm, args: &List) -> MetaResult {
+    let registration_id = try!(require_id(args, 0));
+    match registration_details(realm, registration_id) {
+        Some(details) => Ok((Some(vec![Value::Dict(details)]), None)),
+        None => Err(CallError::new(Reason::NoSuchRegistration, None, None)),
+    }
+}
+
+/// `wamp.registration.list_callees(registration_id)`
+pub fn list_callees(realm: &Realm, args: &List) -> MetaResult {
+    let registration_id = try!(require_id(args, 0));
+    match callees(realm, registration_id) {
+        Some(callees) => Ok((
+            Some(vec![Value::List(
+                callees.into_iter().map(Value::UnsignedInteger).collect(),
+            )]),
+            None,
+        )),
+        None => Err(CallError::new(Reason::NoSuchRegistration, None, None)),
+    }
+}
+
+/// `wamp.registration.count_callees(registration_id)`
+pub fn count_callees(realm: &Realm, args: &List) -> MetaResult {
+    let registration_id = try!(require_id(args, 0));
+    match callees(realm, registration_id) {
+        Some(callees) => Ok((Some(vec![Value::UnsignedInteger(callees.len() as u64)]), None)),
+        None => Err(CallError::new(Reason::NoSuchRegistration, None, None)),
+    }
+}
@@ -0,0 +1,291 @@
+use super::{publish_meta_event, require_id, require_string, MetaResult};
+use messages::{ErrorDetails, ErrorType, Message, PublishOptions, Reason, URI};
+use router::authorization::Action;
+use router::messaging::send_message;
+use router::{random_id, ConnectionHandler, ConnectionInfo, ConnectionState, Realm, Testament,
+             TestamentScope};
+use serde_json;
+use std::collections::HashMap;
+use std::mem;
+use {ArgDict, CallError, CallResult, Dict, List, Value, ID};
+
+/// Describes a session the way the session meta API reports it.
+pub fn session_details(info: &ConnectionInfo) -> Dict {
+    let mut details = HashMap::new();
+    details.insert("session".to_string(), Value::UnsignedInteger(info.id));
+    if let Some(ref authid) = info.authid {
+        details.insert("authid".to_string(), Value::String(authid.clone()));
+    }
+    if let Some(ref authrole) = info.authrole {
+        details.insert("authrole".to_string(), Value::String(authrole.clone()));
+    }
+    let mut transport = HashMap::new();
+    transport.insert("type".to_string(), Value::String(info.transport_type.to_string()));
+    transport.insert("protocol".to_string(), Value::String(info.serializer.protocol().to_string()));
+    details.insert("transport".to_string(), Value::Dict(transport));
+    details
+}
+
+/// Publishes `wamp.session.on_join` for a session that just joined the realm.
+pub fn on_join(realm: &Realm, details: Dict) {
+    publish_meta_event(realm, "wamp.session.on_join", vec![Value::Dict(details)]);
+}
+
+/// Publishes `wamp.session.on_leave` for a session that just left the realm.
+pub fn on_leave(realm: &Realm, id: ID, authid: Option<String>, authrole: Option<String>) {
+    publish_meta_event(
+        realm,
+        "wamp.session.on_leave",
+        vec![
+            Value::UnsignedInteger(id),
+            Value::String(authid.unwrap_or_default()),
+            Value::String(authrole.unwrap_or_default()),
+        ],
+    );
+}
+
+/// `wamp.session.count(filter_authroles)`
+pub fn count(realm: &Realm, args: &List) -> MetaResult {
+    let sessions = try!(filtered_sessions(realm, args));
+    Ok((
+        Some(vec![Value::UnsignedInteger(sessions.len() as u64)]),
+        None,
+    ))
+}
+
+/// `wamp.session.list(filter_authroles)`
+pub fn list(realm: &Realm, args: &List) -> MetaResult {
+    let sessions = try!(filtered_sessions(realm, args));
+    let sessions = sessions.into_iter().map(Value::UnsignedInteger).collect();
+    Ok((Some(vec![Value::List(sessions)]), None))
+}
+
+/// `wamp.session.get(session_id)`
+pub fn get(realm: &Realm, args: &List) -> MetaResult {
+    let session_id = try!(require_id(args, 0));
+    for connection in &realm.connections {
+        let info = connection.lock().unwrap();
+        if info.id == session_id {
+            return Ok((Some(vec![Value::Dict(session_details(&info))]), None));
+        }
+    }
+    Err(CallError::new(Reason::NoSuchSession, None, None))
+}
+
+/// `wamp.session.kill(session_id, reason=, message=)`
+///
+/// Sends a GOODBYE to the session.  A session may not kill itself.
+pub fn kill(realm: &Realm, caller_id: ID, args: &List, kwargs: &Dict) -> MetaResult {
+    let session_id = try!(require_id(args, 0));
+    if session_id == caller_id {
+        return Err(CallError::new(
+            Reason::InvalidArgument,
+            Some(vec![Value::String(
+                "A session cannot kill itself".to_string(),
+            )]),
+            None,
+        ));
+    }
+    let reason = match try!(kwargs.get_string("reason")) {
+        Some(reason) => Reason::CustomReason(URI::new(reason)),
+        None => Reason::CustomReason(URI::new("wamp.close.killed")),
+    };
+    let details = match try!(kwargs.get_string("message")) {
+        Some(message) => ErrorDetails::new_with_message(message),
+        None => ErrorDetails::new(),
+    };
+    let connection = realm
+        .connections
+        .iter()
+        .find(|connection| connection.lock().unwrap().id == session_id);
+    match connection {
+        Some(connection) => {
+            send_message(connection, &Message::Goodbye(details, reason)).ok();
+            connection.lock().unwrap().state = ConnectionState::ShuttingDown;
+            Ok((None, None))
+        }
+        None => Err(CallError::new(Reason::NoSuchSession, None, None)),
+    }
+}
+
+/// `wamp.session.add_testament(topic, args, kwargs, publish_options=, scope=)`
+///
+/// Stores an event to be published if the session is lost without saying goodbye.  Since the
+/// router does not resume sessions, testaments in both the "destroyed" and "detached" scopes
+/// are published when the transport is lost.
+pub fn add_testament(testaments: &mut Vec<Testament>, args: &List, kwargs: &Dict) -> MetaResult {
+    let topic = URI::new(try!(require_string(args, 0)));
+    let event_args = match args.get(1) {
+        Some(&Value::List(ref event_args)) => Some(event_args.clone()),
+        _ => None,
+    };
+    let event_kwargs = match args.get(2) {
+        Some(&Value::Dict(ref event_kwargs)) => Some(event_kwargs.clone()),
+        _ => None,
+    };
+    let options = match kwargs.get("publish_options") {
+        Some(options) => match serde_json::to_value(options).and_then(serde_json::from_value) {
+            Ok(options) => options,
+            Err(e) => {
+                return Err(CallError::new(
+                    Reason::InvalidArgument,
+                    Some(vec![Value::String(format!("Invalid publish options: {}", e))]),
+                    None,
+                ))
+            }
+        },
+        None => PublishOptions::new(false),
+    };
+    let scope = try!(get_scope(kwargs));
+    testaments.push(Testament {
+        topic: topic,
+        args: event_args,
+        kwargs: event_kwargs,
+        options: options,
+        scope: scope,
+    });
+    Ok((None, None))
+}
+
+/// `wamp.session.flush_testaments(scope=)`
+///
+/// Discards the testaments in the given scope and returns how many there were.
+pub fn flush_testaments(testaments: &mut Vec<Testament>, kwargs: &Dict) -> MetaResult {
+    let scope = try!(get_scope(kwargs));
+    let count = testaments.len();
+    testaments.retain(|testament| testament.scope != scope);
+    Ok((
+        Some(vec![Value::UnsignedInteger((count - testaments.len()) as u64)]),
+        None,
+    ))
+}
+
+fn get_scope(kwargs: &Dict) -> CallResult<TestamentScope> {
+    match try!(kwargs.get_string("scope")) {
+        None | Some("destroyed") => Ok(TestamentScope::Destroyed),
+        Some("detached") => Ok(TestamentScope::Detached),
+        Some(scope) => Err(CallError::new(
+            Reason::InvalidArgument,
+            Some(vec![Value::String(format!(
+                "Invalid testament scope: {}",
+                scope
+            ))]),
+            None,
+        )),
+    }
+}
+
+impl ConnectionHandler {
+    /// Decides whether this session may publish the testament it just added.  The authorizer can
+    /// not be asked once the session is lost, so it is asked now and its answer is cached with the
+    /// session's other decisions.  A testament the session is denied is discarded.
+    pub fn authorize_testament(&mut self) -> CallResult<()> {
+        let topic = match self.testaments.last() {
+            Some(testament) => testament.topic.clone(),
+            None => return Ok(()),
+        };
+        match self.authorize(&topic, Action::Publish, ErrorType::Publish, random_id()) {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                self.testaments.pop();
+                Err(CallError::new(Reason::NotAuthorized, None, None))
+            }
+        }
+    }
+
+    /// Decides whether this session may publish a testament, using only the realm's rules and the
+    /// decisions of the authorizer cached so far.  Returns None if the authorizer has not decided.
+    fn may_publish_testament(&self, topic: &URI) -> Option<bool> {
+        let realm = match self.realm {
+            Some(ref realm) => realm.lock().unwrap(),
+            None => return Some(true),
+        };
+        let info = self.info.lock().unwrap();
+        let authrole = info.authrole.as_ref().map(|authrole| &authrole[..]);
+        if !realm.roles.is_authorized(authrole, topic, Action::Publish) {
+            return Some(false);
+        }
+        match realm.authorizer {
+            Some(_) => info
+                .authorizations
+                .get(&(topic.uri.clone(), Action::Publish))
+                .cloned(),
+            None => Some(true),
+        }
+    }
+
+    /// Publishes the testaments of this session, which is going away without a goodbye.
+    pub fn publish_testaments(&mut self) {
+        let testaments = mem::replace(&mut self.testaments, Vec::new());
+        for testament in testaments {
+            match self.may_publish_testament(&testament.topic) {
+                Some(true) => {
+                    debug!("Publishing testament to {}", testament.topic.uri);
+                    self.handle_publish(
+                        random_id(),
+                        testament.options,
+                        testament.topic,
+                        testament.args,
+                        testament.kwargs,
+                    ).ok();
+                }
+                Some(false) => debug!(
+                    "Discarding testament to {}, which was not authorized",
+                    testament.topic.uri
+                ),
+                None => debug!(
+                    "Discarding testament to {}, which the authorizer had not yet allowed",
+                    testament.topic.uri
+                ),
+            }
+        }
+    }
+}
+
+fn filtered_sessions(realm: &Realm, args: &List) -> CallResult<Vec<ID>> {
+    let authroles = match args.get(0) {
+        Some(&Value::List(ref roles)) => {
+            let mut authroles = Vec::new();
+            for role in roles {
+                match *role {
+                    Value::String(ref role) => authroles.push(role.clone()),
+                    ref value => {
+                        return Err(CallError::new(
+                            Reason::InvalidArgument,
+                            Some(vec![Value::String(format!(
+                                "Expected string, got {}",
+                                value.summarize()
+                            ))]),
+                            None,
+                        ))
+                    }
+                }
+            }
+            Some(authroles)
+        }
+        Some(value) => {
+            return Err(CallError::new(
+                Reason::InvalidArgument,
+                Some(vec![Value::String(format!(
+                    "Expected list, got {}",
+                    value.summarize()
+                ))]),
+                None,
+            ))
+        }
+        None => None,
+    };
+    Ok(realm
+        .connections
+        .iter()
+        .map(|connection| connection.lock().unwrap())
+        .filter(|info| match authroles {
+            Some(ref authroles) => match info.authrole {
+                Some(ref authrole) => authroles.contains(authrole),
+                None => false,
+            },
+            None => true,
+        })
+        .map(|info| info.id)
+        .collect())
+}
@@ -0,0 +1,246 @@
+use super::{get_id, match_policy_name, publish_meta_event, require_id, require_string,
+            MetaResult};
+use messages::Reason;
+use router::Realm;
+use std::collections::HashMap;
+use {ArgDict, CallError, Dict, List, MatchingPolicy, Value, ID, URI};
+
+/// Describes the subscription with the given id, if it currently has any subscribers.
+fn subscription_details(realm: &Realm, subscription_id: ID) -> Option<Dict> {
+    let manager = &realm.subscription_manager;
+    let &(ref uri, is_prefix) = match manager.subscription_ids_to_uris.get(&subscription_id) {
+        Some(entry) => entry,
+        None => return None,
+    };
+    manager
+        .subscriptions
+        .get_subscription(uri, is_prefix)
+        .map(|(id, policy, _)| {
+            let mut details = HashMap::new();
+            details.insert("id".to_string(), Value::UnsignedInteger(id));
+            details.insert("uri".to_string(), Value::String(uri.clone()));
+            details.insert(
+                "match".to_string(),
+                Value::String(match_policy_name(policy).to_string()),
+            );
+            details
+        })
+}
+
+/// Gets the session ids of the subscribers of the subscription with the given id.
+fn subscribers(realm: &Realm, subscription_id: ID) -> Option<Vec<ID>> {
+    let manager = &realm.subscription_manager;
+    let &(ref uri, is_prefix) = match manager.subscription_ids_to_uris.get(&subscription_id) {
+        Some(entry) => entry,
+        None => return None,
+    };
+    manager
+        .subscriptions
+        .get_subscription(uri, is_prefix)
+        .map(|(_, _, subscribers)| {
+            subscribers
+                .iter()
+                .map(|subscriber| subscriber.lock().unwrap().id)
+                .collect()
+        })
+}
+
+/// Publishes `wamp.subscription.on_subscribe`, preceded by `wamp.subscription.on_create` if the
+/// session is the first subscriber of the subscription.
+pub fn on_subscribe(realm: &Realm, session_id: ID, subscription_id: ID, created: bool) {
+    if created {
+        if let Some(details) = subscription_details(realm, subscription_id) {
+            publish_meta_event(
+                realm,
+                "wamp.subscription.on_create",
+                vec![Value::UnsignedInteger(session_id), Value::Dict(details)],
+            );
+        }
+    }
+    publish_meta_event(
+        realm,
+        "wamp.subscription.on_subscribe",
+        vec![
+            Value::UnsignedInteger(session_id),
+            Value::UnsignedInteger(subscription_id),
+        ],
+    );
+}
+
+/// Publishes `wamp.subscription.on_unsubscribe`, followed by `wamp.subscription.on_delete` if
+/// the session was the last subscriber of the subscription.
+pub fn on_unsubscribe(realm: &Realm, session_id: ID, subscription_id: ID) {
+    let args = vec![
+        Value::UnsignedInteger(session_id),
+        Value::UnsignedInteger(subscription_id),
+    ];
+    publish_meta_event(realm, "wamp.subscription.on_unsubscribe", args.clone());
+    if subscribers(realm, subscription_id).is_none() {
+        publish_meta_event(realm, "wamp.subscription.on_delete", args);
+    }
+}
+
+/// Checks whether a subscription with exactly the given pattern currently has subscribers.
+pub fn is_subscribed(realm: &Realm, topic: &URI, policy: MatchingPolicy) -> bool {
+    realm
+        .subscription_manager
+        .subscriptions
+        .get_subscription(&topic.uri, policy == MatchingPolicy::Prefix)
+        .is_some()
+}
+
+/// `wamp.subscription.list()`
+pub fn list(realm: &Realm) -> MetaResult {
+    let mut exact = Vec::new();
+    let mut prefix = Vec::new();
+    let mut wildcard = Vec::new();
+    let manager = &realm.subscription_manager;
+    for &(ref uri, is_prefix) in manager.subscription_ids_to_uris.values() {
+        if let Some((id, policy, _)) = manager.subscriptions.get_subscription(uri, is_prefix) {
+            match policy {
+                MatchingPolicy::Strict => exact.push(Value::UnsignedInteger(id)),
+                MatchingPolicy::Prefix => prefix.push(Value::UnsignedInteger(id)),
+                MatchingPolicy::Wildcard => wildcard.push(Value::UnsignedInteger(id)),
+            }
+        }
+    }
+    let mut subscriptions = HashMap::new();
+    subscriptions.insert("exact".to_string(), Value::List(exact));
+    subscriptions.insert("prefix".to_string(), Value::List(prefix));
+    subscriptions.insert("wildcard".to_string(), Value::List(wildcard));
+    Ok((Some(vec![Value::Dict(subscriptions)]), None))
+}
+
+/// `wamp.subscription.lookup(topic, options)`
+///
+/// Returns nothing if there is no subscription with exactly the given pattern.
+pub fn lookup(realm: &Realm, args: &List) -> MetaResult {
+    let topic = try!(require_string(args, 0));
+    let is_prefix = match args.get(1) {
+        Some(&Value::Dict(ref options)) => try!(options.get_string("match")) == Some("prefix"),
+        _ => false,
+    };
+    let subscription = realm
+        .subscription_manager
+        .subscriptions
+        .get_subscription(topic, is_prefix);
+    match subscription {
+        Some((id, _, _)) => Ok((Some(vec![Value::UnsignedInteger(id)]), None)),
+        None => Ok((None, None)),
+    }
+}
+
+/// `wamp.subscription.match(topic)`
+///
+/// Returns the ids of every subscription an event published to the topic would be sent to.
+pub fn match_topic(realm: &Realm, args: &List) -> MetaResult {
+    let topic = try!(require_string(args, 0));
+    let mut subscription_ids = Vec::new();
+    for (_, id, _) in realm
+        .subscription_manager
+        .subscriptions
+        .filter(URI::new(topic))
+    {
+        if !subscription_ids.contains(&id) {
+            subscription_ids.push(id);
+        }
+    }
+    Ok((
+        Some(vec![Value::List(
+            subscription_ids
+                .into_iter()
+                .map(Value::UnsignedInteger)
+                .collect(),
+        )]),
+        None,
+    ))
+}
+
+/// `wamp.subscription.get(subscription_id)`
+pub fn get(realm: &Realm, args: &List) -> MetaResult {
+    let subscription_id = try!(require_id(args, 0));
+    match subscription_details(realm, subscription_id) {
+        Some(details) => Ok((Some(vec![Value::Dict(details)]), None)),
+        None => Err(CallError::new(Reason::NoSuchSubscription, None, None)),
+    }
+}
+
+/// `wamp.subscription.list_subscribers(subscription_id)`
+pub fn list_subscribers(realm: &Realm, args: &List) -> MetaResult {
+    let subscription_id = try!(require_id(args, 0));
+    match subscribers(realm, subscription_id) {
+        Some(subscribers) => Ok((
+            Some(vec![Value::List(
+                subscribers.into_iter().map(Value::UnsignedInteger).collect(),
+            )]),
+            None,
+        )),
+        None => Err(CallError::new(Reason::NoSuchSubscription, None, None)),
+    }
+}
+
+/// `wamp.subscription.count_subscribers(subscription_id)`
+pub fn count_subscribers(realm: &Realm, args: &List) -> MetaResult {
+    let subscription_id = try!(require_id(args, 0));
+    match subscribers(realm, subscription_id) {
+        Some(subscribers) => Ok((
+            Some(vec![Value::UnsignedInteger(subscribers.len() as u64)]),
+            None,
+        )),
+        None => Err(CallError::new(Reason::NoSuchSubscription, None, None)),
+    }
+}
+
+/// `wamp.subscription.get_events(subscription_id, limit)`
+///
+/// Returns the most recent events published to the subscription, oldest first.  If no limit is
+/// given, the whole history kept by the realm is returned.
+pub fn get_events(realm: &Realm, args: &List) -> MetaResult {
+    let subscription_id = try!(require_id(args, 0));
+    let limit = try!(get_id(args, 1));
+    let manager = &realm.subscription_manager;
+    if !manager
+        .subscription_ids_to_uris
+        .contains_key(&subscription_id)
+    {
+        return Err(CallError::new(Reason::NoSuchSubscription, None, None));
+    }
+    let events = match manager.event_history.get(&subscription_id) {
+        Some(history) => {
+            let skip = match limit {
+                Some(limit) if (limit as usize) < history.len() => history.len() - limit as usize,
+                _ => 0,
+            };
+            history
+                .iter()
+                .skip(skip)
+                .map(|event| {
+                    let mut dict = HashMap::new();
+                    dict.insert(
+                        "subscription".to_string(),
+                        Value::UnsignedInteger(subscription_id),
+                    );
+                    dict.insert(
+                        "publication".to_string(),
+                        Value::UnsignedInteger(event.publication_id),
+                    );
+                    dict.insert(
+                        "topic".to_string(),
+                        Value::String(event.topic.uri.clone()),
+                    );
+                    dict.insert(
+                        "args".to_string(),
+                        Value::List(event.args.clone().unwrap_or_default()),
+                    );
+                    dict.insert(
+                        "kwargs".to_string(),
+                        Value::Dict(event.kwargs.clone().unwrap_or_default()),
+                    );
+                    Value::Dict(dict)
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    Ok((Some(vec![Value::List(events)]), None))
+}
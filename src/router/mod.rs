@@ -1,37 +1,114 @@
+mod auth;
+mod authorization;
 mod handshake;
 mod messaging;
+mod meta;
+mod negotiation;
 mod pubsub;
 mod rpc;
 
-use super::ID;
-use messages::{ErrorDetails, Message, Reason};
+use super::{Dict, List, MatchingPolicy, ID};
+use messages::{ClientRoles, ErrorDetails, EventDetails, HelloDetails, Message, PublishOptions,
+               Reason, URI};
 use rand::distributions::{Distribution, Range};
 use rand::thread_rng;
+use router::auth::{forget_pending_authentications, CredentialStore, Identity, PendingAuth,
+                   PendingAuthentication};
+use router::authorization::{forget_pending_authorizations, PendingAuthorization, RoleStore};
 use router::messaging::send_message;
-use router::pubsub::SubscriptionPatternNode;
-use router::rpc::RegistrationPatternNode;
-use std::collections::HashMap;
+use router::meta::{on_leave, on_unregister, on_unsubscribe};
+use router::pubsub::{forget_deleted_subscription, SubscriptionPatternNode};
+use router::rpc::{CallTimer, RegistrationPatternNode};
+use serializer::{JsonSerializer, Serializer};
+use std::collections::{HashMap, VecDeque};
+#[cfg(unix)]
+use std::fs;
+use std::io;
 use std::marker::Sync;
+use std::mem;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
-use ws::{listen as ws_listen, Result as WSResult, Sender};
+use transport::longpoll;
+use transport::rawsocket::{self, Socket};
+use transport::{self, Transport};
+use ws::{listen as ws_listen, Result as WSResult};
+
+pub use router::auth::TicketValidator;
+pub use router::authorization::{Action, Permissions};
 
 struct SubscriptionManager {
     subscriptions: SubscriptionPatternNode<Arc<Mutex<ConnectionInfo>>>,
     subscription_ids_to_uris: HashMap<u64, (String, bool)>,
+    retained_events: HashMap<String, RetainedEvent>,
+    event_history: HashMap<ID, VecDeque<StoredEvent>>,
+    history_limit: usize,
+}
+
+#[derive(Clone)]
+struct StoredEvent {
+    publication_id: ID,
+    topic: URI,
+    details: EventDetails,
+    args: Option<List>,
+    kwargs: Option<Dict>,
+}
+
+/// The last event published to a topic with `retain`, kept with the options it was published
+/// with so that late subscribers are filtered the same way.
+struct RetainedEvent {
+    event: StoredEvent,
+    publisher: ID,
+    options: PublishOptions,
 }
 
 struct RegistrationManager {
     registrations: RegistrationPatternNode<Arc<Mutex<ConnectionInfo>>>,
     registration_ids_to_uris: HashMap<u64, (String, bool)>,
-    active_calls: HashMap<ID, (ID, Arc<Mutex<ConnectionInfo>>)>,
+    active_calls: HashMap<ID, ActiveCall>,
+    pending_authorizations: HashMap<ID, PendingAuthorization>,
+    pending_authentications: HashMap<ID, PendingAuthentication>,
+}
+
+struct ActiveCall {
+    call_id: ID,
+    caller: Arc<Mutex<ConnectionInfo>>,
+    callee: Arc<Mutex<ConnectionInfo>>,
+    receive_progress: bool,
+}
+
+/// An event that the router publishes on behalf of a session when the session is lost without
+/// saying goodbye.
+struct Testament {
+    topic: URI,
+    args: Option<List>,
+    kwargs: Option<Dict>,
+    options: PublishOptions,
+    scope: TestamentScope,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TestamentScope {
+    Destroyed,
+    Detached,
 }
 
 struct Realm {
     subscription_manager: SubscriptionManager,
     registration_manager: RegistrationManager,
     connections: Vec<Arc<Mutex<ConnectionInfo>>>,
+    allow_caller_disclosure: bool,
+    allow_publisher_disclosure: bool,
+    trustlevel: Option<u64>,
+    credentials: CredentialStore,
+    roles: RoleStore,
+    authorizer: Option<URI>,
+    authenticator: Option<URI>,
 }
 
 pub struct Router {
@@ -40,6 +117,7 @@ pub struct Router {
 
 struct RouterInfo {
     realms: Mutex<HashMap<String, Arc<Mutex<Realm>>>>,
+    call_timer: CallTimer,
 }
 
 struct ConnectionHandler {
@@ -48,13 +126,24 @@ struct ConnectionHandler {
     realm: Option<Arc<Mutex<Realm>>>,
     subscribed_topics: Vec<ID>,
     registered_procedures: Vec<ID>,
+    testaments: Vec<Testament>,
+    deferred: VecDeque<Message>,
+    pending_auth: Option<(Arc<Mutex<Realm>>, PendingAuth)>,
+    delegated_ticket: Option<(Arc<Mutex<Realm>>, URI, HelloDetails)>,
+    authenticating: Option<(Arc<Mutex<Realm>>, ID)>,
 }
 
 pub struct ConnectionInfo {
     state: ConnectionState,
-    sender: Sender,
-    protocol: String,
+    sender: Box<Transport>,
+    serializer: &'static Serializer,
+    transport_type: &'static str,
     id: u64,
+    authid: Option<String>,
+    authrole: Option<String>,
+    authorizations: HashMap<(String, Action), bool>,
+    authentication: Option<Result<Identity, Reason>>,
+    roles: ClientRoles,
 }
 
 #[derive(Clone, PartialEq)]
@@ -65,9 +154,6 @@ enum ConnectionState {
     Disconnected,
 }
 
-static WAMP_JSON: &'static str = "wamp.2.json";
-static WAMP_MSGPACK: &'static str = "wamp.2.msgpack";
-
 fn random_id() -> u64 {
     let mut rng = thread_rng();
     // TODO make this a constant
@@ -89,6 +175,7 @@ impl Router {
         Router {
             info: Arc::new(RouterInfo {
                 realms: Mutex::new(HashMap::new()),
+                call_timer: CallTimer::new(),
             }),
         }
     }
@@ -97,21 +184,79 @@ impl Router {
         let router_info = Arc::clone(&self.info);
         let url = url.to_string();
         thread::spawn(move || {
-            ws_listen(&url[..], |sender| ConnectionHandler {
-                info: Arc::new(Mutex::new(ConnectionInfo {
-                    state: ConnectionState::Initializing,
-                    sender: sender,
-                    protocol: String::new(),
-                    id: random_id(),
-                })),
-                subscribed_topics: Vec::new(),
-                registered_procedures: Vec::new(),
-                realm: None,
-                router: Arc::clone(&router_info),
+            ws_listen(&url[..], |sender| {
+                ConnectionHandler::new(&router_info, Box::new(sender), &JsonSerializer, "websocket")
             }).unwrap();
         })
     }
 
+    /// Listens for RawSocket connections on the given TCP address, such as `127.0.0.1:8081`.
+    /// RawSocket carries the same messages as a WebSocket, without the HTTP upgrade and
+    /// WebSocket framing.  Fails if the address can not be bound.
+    pub fn listen_rawsocket(&self, address: &str) -> io::Result<JoinHandle<()>> {
+        let router_info = Arc::clone(&self.info);
+        let listener = try!(TcpListener::bind(address));
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let router_info = Arc::clone(&router_info);
+                        thread::spawn(move || serve_rawsocket(&router_info, stream));
+                    }
+                    Err(e) => warn!("Could not accept RawSocket connection: {}", e),
+                }
+            }
+        }))
+    }
+
+    /// Listens for HTTP long-poll connections on the given TCP address, such as
+    /// `127.0.0.1:8082`, for clients whose proxies do not pass WebSocket upgrades through.  The
+    /// transports are served below any base path, such as `http://127.0.0.1:8082/lp/open`.
+    /// Fails if the address can not be bound.
+    pub fn listen_longpoll(&self, address: &str) -> io::Result<JoinHandle<()>> {
+        let router_info = Arc::clone(&self.info);
+        let listener = try!(TcpListener::bind(address));
+        Ok(thread::spawn(move || {
+            longpoll::serve(listener, move |sender, events, serializer| {
+                let router_info = Arc::clone(&router_info);
+                thread::spawn(move || {
+                    let mut handler = ConnectionHandler::new(
+                        &router_info,
+                        Box::new(sender.clone()),
+                        serializer,
+                        "longpoll",
+                    );
+                    transport::run(&mut handler, events, &sender);
+                });
+            });
+        }))
+    }
+
+    /// Listens for RawSocket connections on a Unix domain socket at the given path, for
+    /// sessions running on the same host.  A socket left behind at the path by an earlier router
+    /// is replaced.  Fails if the socket can not be created at the path.
+    #[cfg(unix)]
+    pub fn listen_unix(&self, path: &str) -> io::Result<JoinHandle<()>> {
+        let router_info = Arc::clone(&self.info);
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                try!(fs::remove_file(path));
+            }
+        }
+        let listener = try!(UnixListener::bind(path));
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let router_info = Arc::clone(&router_info);
+                        thread::spawn(move || serve_rawsocket(&router_info, stream));
+                    }
+                    Err(e) => warn!("Could not accept Unix socket connection: {}", e),
+                }
+            }
+        }))
+    }
+
     pub fn add_realm(&mut self, realm: &str) {
         let mut realms = self.info.realms.lock().unwrap();
         if realms.contains_key(realm) {
@@ -124,17 +269,181 @@ impl Router {
                 subscription_manager: SubscriptionManager {
                     subscriptions: SubscriptionPatternNode::new(),
                     subscription_ids_to_uris: HashMap::new(),
+                    retained_events: HashMap::new(),
+                    event_history: HashMap::new(),
+                    history_limit: 0,
                 },
                 registration_manager: RegistrationManager {
                     registrations: RegistrationPatternNode::new(),
                     registration_ids_to_uris: HashMap::new(),
                     active_calls: HashMap::new(),
+                    pending_authorizations: HashMap::new(),
+                    pending_authentications: HashMap::new(),
                 },
+                allow_caller_disclosure: true,
+                allow_publisher_disclosure: true,
+                trustlevel: None,
+                credentials: CredentialStore::new(),
+                roles: RoleStore::new(),
+                authorizer: None,
+                authenticator: None,
             })),
         );
         debug!("Added realm {}", realm);
     }
 
+    /// Sets whether callers in the given realm may disclose their identity to callees using the
+    /// `disclose_me` call option.  Disclosure is allowed by default.
+    pub fn set_caller_disclosure(&mut self, realm: &str, allow: bool) {
+        self.configure_realm(realm, |realm| realm.allow_caller_disclosure = allow);
+    }
+
+    /// Sets whether publishers in the given realm may disclose their identity to subscribers
+    /// using the `disclose_me` publish option.  Disclosure is allowed by default.
+    pub fn set_publisher_disclosure(&mut self, realm: &str, allow: bool) {
+        self.configure_realm(realm, |realm| realm.allow_publisher_disclosure = allow);
+    }
+
+    /// Sets the trust level attached to every event published in the given realm.
+    pub fn set_trustlevel(&mut self, realm: &str, trustlevel: Option<u64>) {
+        self.configure_realm(realm, |realm| realm.trustlevel = trustlevel);
+    }
+
+    /// Sets the number of events kept for each subscription in the given realm.  The history
+    /// can be queried with the `wamp.subscription.get_events` meta procedure.  No history is kept
+    /// by default.
+    pub fn set_event_history(&mut self, realm: &str, limit: usize) {
+        self.configure_realm(realm, |realm| {
+            let manager = &mut realm.subscription_manager;
+            manager.history_limit = limit;
+            for history in manager.event_history.values_mut() {
+                while history.len() > limit {
+                    history.pop_front();
+                }
+            }
+        });
+    }
+
+    /// Sets whether sessions may join the given realm without authenticating.  Anonymous access
+    /// is allowed by default.
+    pub fn set_anonymous_access(&mut self, realm: &str, allow: bool) {
+        self.configure_realm(realm, |realm| realm.credentials.set_anonymous_access(allow));
+    }
+
+    /// Adds a user that can join the given realm using WAMP-CRA.
+    pub fn add_wampcra_user(&mut self, realm: &str, authid: &str, secret: &str, authrole: &str) {
+        self.configure_realm(realm, |realm| {
+            realm.credentials.add_wampcra_user(authid, secret, authrole)
+        });
+    }
+
+    /// Adds a user that can join the given realm using WAMP-CRA with a salted secret.  The
+    /// client derives the key from the secret with PBKDF2, using the given salt, number of
+    /// iterations and key length.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    pub fn add_salted_wampcra_user(
+        &mut self,
+        realm: &str,
+        authid: &str,
+        secret: &str,
+        salt: &str,
+        iterations: u32,
+        keylen: usize,
+        authrole: &str,
+    ) {
+        self.configure_realm(realm, |realm| {
+            realm.credentials.add_salted_wampcra_user(
+                authid, secret, salt, iterations, keylen, authrole,
+            )
+        });
+    }
+
+    /// Allows sessions to join the given realm by presenting a ticket, which is checked by the
+    /// given validator.
+    pub fn set_ticket_validator<V>(&mut self, realm: &str, validator: V)
+    where
+        V: TicketValidator + 'static,
+    {
+        self.configure_realm(realm, |realm| realm.credentials.set_ticket_validator(validator));
+    }
+
+    /// Allows the owner of the given hex encoded Ed25519 public key to join the given realm using
+    /// WAMP-Cryptosign.
+    pub fn add_cryptosign_key(
+        &mut self,
+        realm: &str,
+        public_key: &str,
+        authid: &str,
+        authrole: &str,
+    ) {
+        self.configure_realm(realm, |realm| {
+            realm.credentials.add_cryptosign_key(public_key, authid, authrole)
+        });
+    }
+
+    /// Adds a user that can join the given realm using WAMP-SCRAM.  The router keeps only the
+    /// keys derived from the password, never the password itself.
+    pub fn add_scram_user(&mut self, realm: &str, authid: &str, password: &str, authrole: &str) {
+        self.configure_realm(realm, |realm| {
+            realm.credentials.add_scram_user(authid, password, authrole)
+        });
+    }
+
+    /// Adds a permission rule to a role in the given realm.  The rule applies to every URI that
+    /// matches the pattern using the given matching policy.
+    ///
+    /// Once a realm has any rules, sessions may only perform the actions their role's rules
+    /// allow, and sessions whose role has no matching rule are denied.
+    pub fn add_permission(
+        &mut self,
+        realm: &str,
+        authrole: &str,
+        uri: &str,
+        policy: MatchingPolicy,
+        permissions: Permissions,
+    ) {
+        self.configure_realm(realm, |realm| {
+            if let Err(reason) =
+                realm
+                    .roles
+                    .add_permission(authrole, &URI::new(uri), policy, permissions)
+            {
+                warn!("Could not add permission for {} to {}: {:?}", authrole, uri, reason);
+            }
+        });
+    }
+
+    /// Delegates authorization in the given realm to a procedure, which is called with the
+    /// session's details, the URI and the action ("publish", "subscribe", "call" or "register"),
+    /// and returns whether the action is allowed.  Decisions are cached for the rest of each
+    /// session.
+    ///
+    /// Permission rules added with `add_permission` are still checked before the authorizer.
+    pub fn set_authorizer(&mut self, realm: &str, procedure: &str) {
+        self.configure_realm(realm, |realm| realm.authorizer = Some(URI::new(procedure)));
+    }
+
+    /// Delegates authentication in the given realm to a procedure.  Sessions that the realm's
+    /// own credentials can not authenticate are passed to the procedure, which is called with the
+    /// realm and the session's details, and either returns the session's identity or fails to
+    /// reject the session.
+    ///
+    /// The session that provides the procedure must itself join the realm using the realm's own
+    /// credentials.
+    pub fn set_authenticator(&mut self, realm: &str, procedure: &str) {
+        self.configure_realm(realm, |realm| realm.authenticator = Some(URI::new(procedure)));
+    }
+
+    fn configure_realm<F>(&self, realm: &str, configure: F)
+    where
+        F: FnOnce(&mut Realm),
+    {
+        match self.info.realms.lock().unwrap().get(realm) {
+            Some(realm) => configure(&mut realm.lock().unwrap()),
+            None => warn!("Tried to configure realm {}, which does not exist", realm),
+        }
+    }
+
     pub fn shutdown(&self) {
         for realm in self.info.realms.lock().unwrap().values() {
             for connection in &realm.lock().unwrap().connections {
@@ -157,9 +466,74 @@ impl Router {
     }
 }
 
+/// Runs a RawSocket connection on the current thread until it closes.
+fn serve_rawsocket<S: Socket>(router_info: &Arc<RouterInfo>, mut stream: S) {
+    let opened = rawsocket::accept(&mut stream).and_then(|(format, max_length)| {
+        rawsocket::open(stream, format, max_length).map(|(sender, events)| (format, sender, events))
+    });
+    let (format, sender, events) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            warn!("RawSocket handshake failed: {}", e);
+            return;
+        }
+    };
+    let mut handler = ConnectionHandler::new(
+        router_info,
+        Box::new(sender.clone()),
+        format.message_serializer(),
+        "rawsocket",
+    );
+    transport::run(&mut handler, events, &sender);
+}
+
 impl ConnectionHandler {
+    fn new(
+        router_info: &Arc<RouterInfo>,
+        sender: Box<Transport>,
+        serializer: &'static Serializer,
+        transport_type: &'static str,
+    ) -> Self {
+        ConnectionHandler {
+            info: Arc::new(Mutex::new(ConnectionInfo {
+                state: ConnectionState::Initializing,
+                sender: sender,
+                serializer: serializer,
+                transport_type: transport_type,
+                id: random_id(),
+                authid: None,
+                authrole: None,
+                authorizations: HashMap::new(),
+                authentication: None,
+                roles: ClientRoles::new_basic(),
+            })),
+            subscribed_topics: Vec::new(),
+            registered_procedures: Vec::new(),
+            testaments: Vec::new(),
+            deferred: VecDeque::new(),
+            pending_auth: None,
+            delegated_ticket: None,
+            authenticating: None,
+            realm: None,
+            router: Arc::clone(router_info),
+        }
+    }
+
+    /// Takes the session out of its realm.  Does nothing if the session already left, so it is
+    /// safe to call again once the connection closes.
     fn remove(&mut self) {
-        if let Some(ref realm) = self.realm {
+        let subscribed_topics = mem::replace(&mut self.subscribed_topics, Vec::new());
+        let registered_procedures = mem::replace(&mut self.registered_procedures, Vec::new());
+        self.deferred.clear();
+        self.pending_auth = None;
+        self.delegated_ticket = None;
+        if let Some((realm, _)) = self.authenticating.take() {
+            forget_pending_authentications(
+                &mut realm.lock().unwrap().registration_manager.pending_authentications,
+                &self.info,
+            );
+        }
+        if let Some(realm) = self.realm.take() {
             let mut realm = realm.lock().unwrap();
             {
                 trace!(
@@ -167,7 +541,7 @@ impl ConnectionHandler {
                     self.info.lock().unwrap().id
                 );
                 let manager = &mut realm.subscription_manager;
-                for subscription_id in &self.subscribed_topics {
+                for subscription_id in &subscribed_topics {
                     trace!("Looking for subscription {}", subscription_id);
                     if let Some(&(ref topic_uri, is_prefix)) =
                         manager.subscription_ids_to_uris.get(subscription_id)
@@ -179,11 +553,12 @@ impl ConnectionHandler {
                             .ok();
                         trace!("Subscription tree: {:?}", manager.subscriptions);
                     }
+                    forget_deleted_subscription(manager, *subscription_id);
                 }
             }
             {
                 let manager = &mut realm.registration_manager;
-                for registration_id in &self.registered_procedures {
+                for registration_id in &registered_procedures {
                     if let Some(&(ref topic_uri, is_prefix)) =
                         manager.registration_ids_to_uris.get(registration_id)
                     {
@@ -194,15 +569,253 @@ impl ConnectionHandler {
                     }
                 }
             }
-            let my_id = self.info.lock().unwrap().id;
+            let (my_id, authid, authrole) = {
+                let info = self.info.lock().unwrap();
+                (info.id, info.authid.clone(), info.authrole.clone())
+            };
+            for subscription_id in &subscribed_topics {
+                on_unsubscribe(&realm, my_id, *subscription_id);
+            }
+            for registration_id in &registered_procedures {
+                on_unregister(&realm, my_id, *registration_id);
+            }
+            forget_pending_authorizations(
+                &mut realm.registration_manager.pending_authorizations,
+                &self.info,
+            );
+            forget_pending_authentications(
+                &mut realm.registration_manager.pending_authentications,
+                &self.info,
+            );
             realm
                 .connections
                 .retain(|connection| connection.lock().unwrap().id != my_id);
+            on_leave(&realm, my_id, authid, authrole);
         }
     }
 
     fn terminate_connection(&mut self) -> WSResult<()> {
+        self.publish_testaments();
         self.remove();
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{ConnectionHandler, Router};
+    use router::auth::RESUME_AUTHENTICATION;
+    use router::authorization::RESUME_DEFERRED;
+    use serde_json::{self, Value as JsonValue};
+    use serializer::JsonSerializer;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use transport::testing::Recorder;
+    use ws::{CloseCode, Handler, Message as WSMessage};
+
+    fn connect(router: &Router) -> (ConnectionHandler, Recorder) {
+        let recorder = Recorder::new();
+        let handler = ConnectionHandler::new(
+            &router.info,
+            Box::new(recorder.clone()),
+            &JsonSerializer,
+            "rawsocket",
+        );
+        (handler, recorder)
+    }
+
+    fn join(router: &Router) -> (ConnectionHandler, Recorder) {
+        let (mut handler, sent) = connect(router);
+        let hello = r#"[1,"realm1",{"roles":{"subscriber":{},"caller":{},"callee":{}}}]"#;
+        receive(&mut handler, hello);
+        (handler, sent)
+    }
+
+    fn receive(handler: &mut ConnectionHandler, message: &str) {
+        handler
+            .on_message(WSMessage::Text(message.to_string()))
+            .unwrap();
+    }
+
+    #[test]
+    fn leaving_once() {
+        let mut router = Router::new();
+        router.add_realm("realm1");
+        let (mut observer, observed) = join(&router);
+        receive(&mut observer, r#"[32,1,{},"wamp.session.on_leave"]"#);
+
+        let (mut session, _) = join(&router);
+        receive(&mut session, r#"[32,2,{},"com.example.topic"]"#);
+        session.terminate_connection().unwrap();
+        session.on_close(CloseCode::Abnormal, "");
+        assert_eq!(observed.count("[36,"), 1);
+    }
+
+    #[test]
+    fn answering_while_waiting_for_the_authorizer() {
+        let mut router = Router::new();
+        router.add_realm("realm1");
+        let (mut session, sent) = join(&router);
+        receive(&mut session, r#"[32,1,{},"com.example.topic"]"#);
+        let subscribed: JsonValue = serde_json::from_str(&sent.sent()[1]).unwrap();
+        let subscription = subscribed[2].as_u64().unwrap();
+
+        router.set_authorizer("realm1", "com.example.authorize");
+        let (mut authorizer, asked) = join(&router);
+        receive(&mut authorizer, r#"[64,2,{},"com.example.authorize"]"#);
+        receive(&mut session, r#"[48,3,{},"com.example.procedure",[]]"#);
+        assert_eq!(asked.count("[68,"), 1);
+
+        // Messages that need no authorization are not held back behind the call
+        receive(&mut session, &format!("[34,4,{}]", subscription));
+        assert_eq!(sent.count("[35,4]"), 1);
+
+        // The call is denied once the authorizer is gone
+        authorizer.terminate_connection().unwrap();
+        session.on_timeout(RESUME_DEFERRED).unwrap();
+        assert_eq!(sent.count("[8,48,3,"), 1);
+    }
+
+    #[test]
+    fn authorizing_testaments_before_the_session_is_lost() {
+        let mut router = Router::new();
+        router.add_realm("realm1");
+        let (mut observer, observed) = join(&router);
+        receive(&mut observer, r#"[32,1,{},"com.example.topic"]"#);
+        receive(&mut observer, r#"[32,2,{},"com.example.other"]"#);
+
+        router.set_authorizer("realm1", "com.example.authorize");
+        let (mut authorizer, asked) = join(&router);
+        receive(&mut authorizer, r#"[64,1,{},"com.example.authorize"]"#);
+        let allow = |authorizer: &mut ConnectionHandler, index: usize| {
+            let invocation: JsonValue = serde_json::from_str(&asked.sent()[index]).unwrap();
+            receive(authorizer, &format!("[70,{},{{}},[true]]", invocation[1]));
+        };
+        let (mut session, sent) = join(&router);
+        receive(
+            &mut session,
+            r#"[48,3,{},"wamp.session.add_testament",["com.example.topic",["lost"]]]"#,
+        );
+        allow(&mut authorizer, 2);
+        session.on_timeout(RESUME_DEFERRED).unwrap();
+        assert_eq!(sent.count("[50,3,"), 1);
+        // The authorizer is asked about the topic when the testament is added
+        allow(&mut authorizer, 3);
+
+        // A testament the authorizer has not decided on by the time the session is lost is
+        // discarded, without asking the authorizer again
+        receive(
+            &mut session,
+            r#"[48,4,{},"wamp.session.add_testament",["com.example.other",["lost"]]]"#,
+        );
+        assert_eq!(sent.count("[50,4,"), 1);
+        assert_eq!(asked.count("[68,"), 3);
+        session.terminate_connection().unwrap();
+        assert_eq!(asked.count("[68,"), 3);
+        assert_eq!(observed.count("[36,"), 1);
+        let realm = Arc::clone(&router.info.realms.lock().unwrap()["realm1"]);
+        let realm = realm.lock().unwrap();
+        assert!(realm.registration_manager.pending_authorizations.is_empty());
+    }
+
+    #[test]
+    fn delegating_a_ticket_to_an_authenticator_that_leaves() {
+        let mut router = Router::new();
+        router.add_realm("realm1");
+        router.set_authenticator("realm1", "com.example.authenticate");
+        let (mut authenticator, asked) = join(&router);
+        receive(&mut authenticator, r#"[64,1,{},"com.example.authenticate"]"#);
+
+        let (mut session, sent) = connect(&router);
+        receive(
+            &mut session,
+            r#"[1,"realm1",{"roles":{},"authid":"joe","authmethods":["ticket"]}]"#,
+        );
+        assert_eq!(sent.count(r#"[4,"ticket","#), 1);
+        receive(&mut session, r#"[5,"secret",{}]"#);
+        let invocation: JsonValue = serde_json::from_str(&asked.sent()[2]).unwrap();
+        assert_eq!(invocation[0], 68);
+        assert_eq!(invocation[4][1]["ticket"], "secret");
+        assert_eq!(invocation[4][1]["authid"], "joe");
+
+        // The session is refused once the authenticator is gone
+        authenticator.terminate_connection().unwrap();
+        session.on_timeout(RESUME_AUTHENTICATION).unwrap();
+        assert_eq!(sent.count(r#"[3,{},"wamp.error.authentication_failed"]"#), 1);
+        let realm = Arc::clone(&router.info.realms.lock().unwrap()["realm1"]);
+        let realm = realm.lock().unwrap();
+        assert!(realm.registration_manager.pending_authentications.is_empty());
+    }
+
+    #[test]
+    fn retaining_and_forgetting_events() {
+        let mut router = Router::new();
+        router.add_realm("realm1");
+        router.set_event_history("realm1", 5);
+        let (mut publisher, _) = connect(&router);
+        let hello = r#"[1,"realm1",{"roles":{"publisher":{"features":{
+            "subscriber_blackwhite_listing":true}}}}]"#;
+        receive(&mut publisher, hello);
+        let (mut subscriber, _) = join(&router);
+        receive(&mut subscriber, r#"[32,1,{},"com.example.topic"]"#);
+        receive(&mut publisher, r#"[16,2,{},"com.example.topic",["one"]]"#);
+        receive(&mut subscriber, r#"[32,3,{},"com.example.other"]"#);
+        let realm = Arc::clone(&router.info.realms.lock().unwrap()["realm1"]);
+        assert_eq!(realm.lock().unwrap().subscription_manager.event_history.len(), 1);
+
+        // The history goes once the subscription has no subscribers left
+        subscriber.terminate_connection().unwrap();
+        assert!(realm.lock().unwrap().subscription_manager.event_history.is_empty());
+
+        // The retained event only goes to the sessions it was published for
+        let (mut eligible, eligible_sent) = join(&router);
+        let (mut excluded, excluded_sent) = join(&router);
+        let excluded_id = excluded.info.lock().unwrap().id;
+        let publish = format!(
+            r#"[16,4,{{"retain":true,"exclude":[{}]}},"com.example.topic",["two"]]"#,
+            excluded_id
+        );
+        receive(&mut publisher, &publish);
+        receive(&mut eligible, r#"[32,5,{"get_retained":true},"com.example.topic"]"#);
+        receive(&mut excluded, r#"[32,6,{"get_retained":true},"com.example.topic"]"#);
+        assert_eq!(eligible_sent.count("[36,"), 1);
+        assert_eq!(excluded_sent.count("[36,"), 0);
+    }
+
+    #[test]
+    fn timing_out_calls_in_order_of_their_deadlines() {
+        let mut router = Router::new();
+        router.add_realm("realm1");
+        let (mut callee, invoked) = join(&router);
+        receive(&mut callee, r#"[64,1,{},"com.example.procedure"]"#);
+        let (mut caller, sent) = connect(&router);
+        let hello = r#"[1,"realm1",{"roles":{"caller":{"features":{"call_timeout":true}}}}]"#;
+        receive(&mut caller, hello);
+        receive(&mut caller, r#"[48,2,{"timeout":300},"com.example.procedure",[]]"#);
+        receive(&mut caller, r#"[48,3,{"timeout":100},"com.example.procedure",[]]"#);
+        assert_eq!(invoked.count("[68,"), 2);
+
+        assert!(sent.wait_for("[8,48,", 2, Duration::from_secs(5)));
+        let errors: Vec<String> = sent.sent()
+            .into_iter()
+            .filter(|message| message.starts_with("[8,48,"))
+            .collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("[8,48,3,"));
+        assert!(errors[1].starts_with("[8,48,2,"));
+        assert_eq!(invoked.count("[69,"), 2);
+    }
+
+    #[test]
+    fn reporting_the_transport() {
+        let mut router = Router::new();
+        router.add_realm("realm1");
+        let (mut session, sent) = join(&router);
+        let id = session.info.lock().unwrap().id;
+        receive(&mut session, &format!(r#"[48,1,{{}},"wamp.session.get",[{}]]"#, id));
+        let result: JsonValue = serde_json::from_str(&sent.sent()[1]).unwrap();
+        assert_eq!(result[0], 50);
+        assert_eq!(result[3][0]["transport"]["type"], "rawsocket");
+        assert_eq!(result[3][0]["transport"]["protocol"], "wamp.2.json");
+    }
+}
@@ -0,0 +1,121 @@
+//! Contains the checks that keep sessions from using features the other peer did not announce.
+
+use super::{ConnectionHandler, Realm};
+
+use messages::{ErrorType, InvocationPolicy, MatchingPolicy, Message, RouterRoles};
+use {require_feature, WampResult};
+
+impl Realm {
+    /// The roles announced to sessions joining this realm, leaving out the features the realm
+    /// has disabled.
+    pub fn router_roles(&self) -> RouterRoles {
+        let mut roles = RouterRoles::new();
+        if let Some(ref mut broker) = roles.broker {
+            let features = &mut broker.features;
+            features.publisher_identification = self.allow_publisher_disclosure;
+            features.publication_trustlevels = self.trustlevel.is_some();
+            features.event_history = self.subscription_manager.history_limit > 0;
+        }
+        if let Some(ref mut dealer) = roles.dealer {
+            dealer.features.caller_identification = self.allow_caller_disclosure;
+        }
+        roles
+    }
+}
+
+impl ConnectionHandler {
+    /// Refuses messages that use options the session did not announce support for in its hello
+    /// message.
+    pub fn check_features(&self, message: &Message) -> WampResult<()> {
+        let info = self.info.lock().unwrap();
+        let roles = &info.roles;
+        match *message {
+            Message::Subscribe(request_id, ref options, _) => {
+                let features = &roles.subscriber.features;
+                require_feature(
+                    options.pattern_match == MatchingPolicy::Strict
+                        || features.pattern_based_subscription,
+                    ErrorType::Subscribe,
+                    request_id,
+                )
+            }
+            Message::Publish(request_id, ref options, ..) => {
+                let features = &roles.publisher.features;
+                let listing = options.exclude.is_some() || options.exclude_authid.is_some()
+                    || options.exclude_authrole.is_some()
+                    || options.eligible.is_some()
+                    || options.eligible_authid.is_some()
+                    || options.eligible_authrole.is_some();
+                try!(require_feature(
+                    !options.disclose_me || features.publisher_identification,
+                    ErrorType::Publish,
+                    request_id,
+                ));
+                try!(require_feature(
+                    !listing || features.subscriber_blackwhite_listing,
+                    ErrorType::Publish,
+                    request_id,
+                ));
+                try!(require_feature(
+                    options.exclude_me.is_none() || features.publisher_exclusion,
+                    ErrorType::Publish,
+                    request_id,
+                ));
+                require_feature(
+                    options.ppt_scheme.is_none() || features.payload_passthru_mode,
+                    ErrorType::Publish,
+                    request_id,
+                )
+            }
+            Message::Register(request_id, ref options, _) => {
+                let features = &roles.callee.features;
+                try!(require_feature(
+                    options.pattern_match == MatchingPolicy::Strict
+                        || features.pattern_based_registration,
+                    ErrorType::Register,
+                    request_id,
+                ));
+                require_feature(
+                    options.invocation_policy == InvocationPolicy::Single
+                        || features.shared_registration,
+                    ErrorType::Register,
+                    request_id,
+                )
+            }
+            Message::Call(request_id, ref options, ..) => {
+                let features = &roles.caller.features;
+                try!(require_feature(
+                    !options.receive_progress || features.progressive_call_results,
+                    ErrorType::Call,
+                    request_id,
+                ));
+                try!(require_feature(
+                    options.timeout == 0 || features.call_timeout,
+                    ErrorType::Call,
+                    request_id,
+                ));
+                try!(require_feature(
+                    !options.disclose_me || features.caller_identification,
+                    ErrorType::Call,
+                    request_id,
+                ));
+                require_feature(
+                    options.ppt_scheme.is_none() || features.payload_passthru_mode,
+                    ErrorType::Call,
+                    request_id,
+                )
+            }
+            Message::Cancel(request_id, _) => require_feature(
+                roles.caller.features.call_canceling,
+                ErrorType::Call,
+                request_id,
+            ),
+            Message::Yield(request_id, ref options, ..) => require_feature(
+                options.ppt_scheme.is_none() || roles.callee.features.payload_passthru_mode,
+                ErrorType::Invocation,
+                request_id,
+            ),
+            _ => Ok(()),
+        }
+    }
+}
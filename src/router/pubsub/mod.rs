@@ -1,11 +1,14 @@
 mod patterns;
-use super::{random_id, ConnectionHandler};
+use super::{random_id, ConnectionHandler, ConnectionInfo, RetainedEvent, StoredEvent,
+            SubscriptionManager};
+use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
 
 use messages::{ErrorType, EventDetails, Message, PublishOptions, Reason, SubscribeOptions, URI};
 use router::messaging::send_message;
-pub use router::pubsub::patterns::SubscriptionPatternNode;
-use {Dict, Error, ErrorKind, List, MatchingPolicy, WampResult};
+use router::meta::{is_subscribed, on_subscribe, on_unsubscribe};
+pub use router::pubsub::patterns::{PatternData, SubscriptionPatternNode};
+use {Dict, Error, ErrorKind, List, MatchingPolicy, WampResult, ID};
 
 impl ConnectionHandler {
     pub fn handle_subscribe(
@@ -21,6 +24,7 @@ impl ConnectionHandler {
         match self.realm {
             Some(ref realm) => {
                 let mut realm = realm.lock().unwrap();
+                let created = !is_subscribed(&realm, &topic, options.pattern_match);
                 let manager = &mut realm.subscription_manager;
                 let topic_id = {
                     let topic_id = match manager.subscriptions.subscribe_with(
@@ -44,7 +48,49 @@ impl ConnectionHandler {
                     topic_id,
                     (topic.uri, options.pattern_match == MatchingPolicy::Prefix),
                 );
-                send_message(&self.info, &Message::Subscribed(request_id, topic_id))
+                try!(send_message(
+                    &self.info,
+                    &Message::Subscribed(request_id, topic_id)
+                ));
+                if options.get_retained {
+                    for retained in manager.retained_events.values() {
+                        let event = &retained.event;
+                        let policy = manager
+                            .subscriptions
+                            .filter(event.topic.clone())
+                            .find(|&(_, id, _)| id == topic_id)
+                            .map(|(_, _, policy)| policy);
+                        let policy = match policy {
+                            Some(policy) => policy,
+                            None => continue,
+                        };
+                        if !is_recipient(
+                            &retained.options,
+                            &self.info.lock().unwrap(),
+                            retained.publisher,
+                        ) {
+                            continue;
+                        }
+                        let mut details = event.details.clone();
+                        details.retained = true;
+                        if policy != MatchingPolicy::Strict {
+                            details.topic = Some(event.topic.clone());
+                        }
+                        try!(send_message(
+                            &self.info,
+                            &Message::Event(
+                                topic_id,
+                                event.publication_id,
+                                details,
+                                event.args.clone(),
+                                event.kwargs.clone(),
+                            )
+                        ));
+                    }
+                }
+                let my_id = self.info.lock().unwrap().id;
+                on_subscribe(&realm, my_id, topic_id, created);
+                Ok(())
             }
             None => Err(Error::new(ErrorKind::InvalidState(
                 "Received a message while not attached to a realm",
@@ -82,7 +128,14 @@ impl ConnectionHandler {
                     }
                 };
                 self.subscribed_topics.retain(|id| *id != topic_id);
-                send_message(&self.info, &Message::Unsubscribed(request_id))
+                forget_deleted_subscription(manager, topic_id);
+                try!(send_message(
+                    &self.info,
+                    &Message::Unsubscribed(request_id)
+                ));
+                let my_id = self.info.lock().unwrap().id;
+                on_unsubscribe(&realm, my_id, topic_id);
+                Ok(())
             }
             None => Err(Error::new(ErrorKind::InvalidState(
                 "Received a message while not attached to a realm",
@@ -104,20 +157,43 @@ impl ConnectionHandler {
         );
         match self.realm {
             Some(ref realm) => {
-                let realm = realm.lock().unwrap();
-                let manager = &realm.subscription_manager;
+                let mut realm = realm.lock().unwrap();
+                if options.disclose_me && !realm.allow_publisher_disclosure {
+                    return Err(Error::new(ErrorKind::ErrorReason(
+                        ErrorType::Publish,
+                        request_id,
+                        Reason::OptionDisallowedDiscloseMe,
+                    )));
+                }
                 let publication_id = random_id();
-                let mut event_message = Message::Event(
-                    1,
-                    publication_id,
-                    EventDetails::new(),
-                    args.clone(),
-                    kwargs.clone(),
-                );
-                let my_id = { self.info.lock().unwrap().id };
+                let mut details = EventDetails::new();
+                details.set_trustlevel(realm.trustlevel);
+                details.ppt_scheme = options.ppt_scheme.clone();
+                details.ppt_serializer = options.ppt_serializer.clone();
+                details.ppt_cipher = options.ppt_cipher.clone();
+                details.ppt_keyid = options.ppt_keyid.clone();
+                let my_id = {
+                    let info = self.info.lock().unwrap();
+                    if options.disclose_me {
+                        details.set_publisher(info.id, info.authid.clone(), info.authrole.clone());
+                    }
+                    info.id
+                };
+                let stored_event = StoredEvent {
+                    publication_id: publication_id,
+                    topic: topic.clone(),
+                    details: details.clone(),
+                    args: args.clone(),
+                    kwargs: kwargs.clone(),
+                };
+                let mut event_message =
+                    Message::Event(1, publication_id, details, args.clone(), kwargs.clone());
+                let manager = &mut realm.subscription_manager;
                 info!("Current topic tree: {:?}", manager.subscriptions);
+                let mut matched_subscriptions = HashSet::new();
                 for (subscriber, topic_id, policy) in manager.subscriptions.filter(topic.clone()) {
-                    if subscriber.lock().unwrap().id != my_id {
+                    matched_subscriptions.insert(topic_id);
+                    if is_recipient(&options, &subscriber.lock().unwrap(), my_id) {
                         if let Message::Event(
                             ref mut old_topic,
                             ref _publish_id,
@@ -136,7 +212,30 @@ impl ConnectionHandler {
                         try!(send_message(subscriber, &event_message));
                     }
                 }
-                if options.should_acknowledge() {
+                if manager.history_limit > 0 {
+                    for topic_id in matched_subscriptions {
+                        let history = manager
+                            .event_history
+                            .entry(topic_id)
+                            .or_insert_with(VecDeque::new);
+                        if history.len() >= manager.history_limit {
+                            history.pop_front();
+                        }
+                        history.push_back(stored_event.clone());
+                    }
+                }
+                let acknowledge = options.should_acknowledge();
+                if options.retain {
+                    manager.retained_events.insert(
+                        topic.uri.clone(),
+                        RetainedEvent {
+                            event: stored_event,
+                            publisher: my_id,
+                            options: options,
+                        },
+                    );
+                }
+                if acknowledge {
                     try!(send_message(
                         &self.info,
                         &Message::Published(request_id, publication_id)
@@ -150,3 +249,128 @@ impl ConnectionHandler {
         }
     }
 }
+
+/// Forgets the event history of a subscription once its last subscriber has left.
+pub fn forget_deleted_subscription(manager: &mut SubscriptionManager, subscription_id: ID) {
+    let deleted = match manager.subscription_ids_to_uris.get(&subscription_id) {
+        Some(&(ref uri, is_prefix)) => manager
+            .subscriptions
+            .get_subscription(uri, is_prefix)
+            .is_none(),
+        None => true,
+    };
+    if deleted {
+        manager.event_history.remove(&subscription_id);
+    }
+}
+
+/// Checks whether a subscriber should receive an event, based on the exclusion and eligibility
+/// lists in the publish options.
+fn is_recipient(options: &PublishOptions, subscriber: &ConnectionInfo, publisher_id: u64) -> bool {
+    if subscriber.id == publisher_id && options.excludes_me() {
+        return false;
+    }
+    if let Some(ref exclude) = options.exclude {
+        if exclude.contains(&subscriber.id) {
+            return false;
+        }
+    }
+    if let Some(ref eligible) = options.eligible {
+        if !eligible.contains(&subscriber.id) {
+            return false;
+        }
+    }
+    let authid_allowed = matches_auth_lists(
+        &subscriber.authid,
+        &options.exclude_authid,
+        &options.eligible_authid,
+    );
+    authid_allowed && matches_auth_lists(
+        &subscriber.authrole,
+        &options.exclude_authrole,
+        &options.eligible_authrole,
+    )
+}
+
+fn matches_auth_lists(
+    value: &Option<String>,
+    exclude: &Option<Vec<String>>,
+    eligible: &Option<Vec<String>>,
+) -> bool {
+    if let (&Some(ref value), &Some(ref exclude)) = (value, exclude) {
+        if exclude.contains(value) {
+            return false;
+        }
+    }
+    match *eligible {
+        Some(ref eligible) => match *value {
+            Some(ref value) => eligible.contains(value),
+            None => false,
+        },
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_recipient;
+    use messages::{ClientRoles, PublishOptions};
+    use router::{ConnectionInfo, ConnectionState};
+    use serializer::JsonSerializer;
+    use std::collections::HashMap;
+    use transport::testing::Recorder;
+
+    fn session(id: u64, authid: Option<&str>) -> ConnectionInfo {
+        ConnectionInfo {
+            state: ConnectionState::Connected,
+            sender: Box::new(Recorder::new()),
+            serializer: &JsonSerializer,
+            transport_type: "websocket",
+            id: id,
+            authid: authid.map(|authid| authid.to_string()),
+            authrole: Some("anonymous".to_string()),
+            authorizations: HashMap::new(),
+            authentication: None,
+            roles: ClientRoles::new_basic(),
+        }
+    }
+
+    #[test]
+    fn excluding_the_publisher() {
+        let mut options = PublishOptions::new(false);
+        assert!(!is_recipient(&options, &session(1, None), 1));
+        assert!(is_recipient(&options, &session(2, None), 1));
+
+        options.exclude_me = Some(false);
+        assert!(is_recipient(&options, &session(1, None), 1));
+    }
+
+    #[test]
+    fn combining_eligible_and_exclude_lists() {
+        let mut options = PublishOptions::new(false);
+        options.eligible = Some(vec![2, 3]);
+        options.exclude = Some(vec![3]);
+        assert!(is_recipient(&options, &session(2, None), 1));
+        assert!(!is_recipient(&options, &session(3, None), 1));
+        assert!(!is_recipient(&options, &session(4, None), 1));
+    }
+
+    #[test]
+    fn matching_auth_lists_without_an_authid() {
+        let mut options = PublishOptions::new(false);
+        options.exclude_authid = Some(vec!["joe".to_string()]);
+        assert!(is_recipient(&options, &session(2, None), 1));
+        assert!(!is_recipient(&options, &session(3, Some("joe")), 1));
+
+        options.exclude_authid = None;
+        options.eligible_authid = Some(vec!["joe".to_string()]);
+        assert!(!is_recipient(&options, &session(2, None), 1));
+        assert!(is_recipient(&options, &session(3, Some("joe")), 1));
+
+        options.eligible_authid = None;
+        options.eligible_authrole = Some(vec!["anonymous".to_string()]);
+        assert!(is_recipient(&options, &session(2, None), 1));
+        options.exclude_authrole = Some(vec!["anonymous".to_string()]);
+        assert!(!is_recipient(&options, &session(2, None), 1));
+    }
+}
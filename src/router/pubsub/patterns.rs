@@ -173,6 +173,35 @@ impl<P: PatternData> SubscriptionPatternNode<P> {
         self.remove_subscription(uri_bits, subscriber.get_id(), is_prefix)
     }
 
+    /// Gets the subscription with exactly the given pattern, if it has any subscribers.
+    ///
+    /// Returns the id and matching policy of the subscription, along with its subscribers.
+    pub fn get_subscription(
+        &self,
+        topic: &str,
+        is_prefix: bool,
+    ) -> Option<(ID, MatchingPolicy, Vec<&P>)> {
+        let mut node = self;
+        for uri_bit in topic.split('.') {
+            node = match node.edges.get(uri_bit) {
+                Some(edge) => edge,
+                None => return None,
+            };
+        }
+        let (subscribers, id) = if is_prefix {
+            (&node.prefix_connections, node.prefix_id)
+        } else {
+            (&node.connections, node.id)
+        };
+        subscribers.first().map(|first| {
+            (
+                id,
+                first.policy,
+                subscribers.iter().map(|wrapper| &wrapper.subscriber).collect(),
+            )
+        })
+    }
+
     /// Constructs a new SubscriptionPatternNode to be used as the root of the trie
     #[inline]
     pub fn new() -> SubscriptionPatternNode<P> {
@@ -480,4 +509,40 @@ mod test {
             vec![ids[2], ids[1]]
         )
     }
+
+    #[test]
+    fn inspecting_subscriptions() {
+        let connection1 = MockData::new(1);
+        let connection2 = MockData::new(2);
+        let mut root = SubscriptionPatternNode::new();
+
+        let id = root.subscribe_with(
+            &URI::new("com.example"),
+            connection1.clone(),
+            MatchingPolicy::Prefix,
+        ).unwrap();
+        root.subscribe_with(
+            &URI::new("com.example"),
+            connection2,
+            MatchingPolicy::Prefix,
+        ).unwrap();
+
+        let (subscription_id, policy, subscribers) =
+            root.get_subscription("com.example", true).unwrap();
+        assert_eq!(subscription_id, id);
+        assert_eq!(policy, MatchingPolicy::Prefix);
+        assert_eq!(
+            subscribers.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(root.get_subscription("com.example", false).is_none());
+        assert!(root.get_subscription("com.other", true).is_none());
+
+        root.unsubscribe_with("com.example", &connection1, true)
+            .unwrap();
+        assert_eq!(
+            root.get_subscription("com.example", true).unwrap().2.len(),
+            1
+        );
+    }
 }
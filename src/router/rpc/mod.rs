@@ -1,14 +1,20 @@
 mod patterns;
+mod timer;
 
 use std::sync::Arc;
 
 pub use router::rpc::patterns::RegistrationPatternNode;
+pub use router::rpc::timer::CallTimer;
 
-use super::{random_id, ConnectionHandler};
+use super::{random_id, ActiveCall, ConnectionHandler};
 
-use messages::{CallOptions, ErrorType, InvocationDetails, Message, Reason, RegisterOptions,
-               ResultDetails, YieldOptions, URI};
+use messages::{CallOptions, CancelMode, CancelOptions, ErrorType, InterruptOptions,
+               InvocationDetails, Message, Reason, RegisterOptions, ResultDetails, YieldOptions,
+               URI};
+use std::collections::HashMap;
+use router::authorization::is_allowed;
 use router::messaging::send_message;
+use router::meta::{is_meta_procedure, is_registered, on_register, on_unregister};
 use {Dict, Error, ErrorKind, List, MatchingPolicy, WampResult, ID};
 
 impl ConnectionHandler {
@@ -25,6 +31,7 @@ impl ConnectionHandler {
         match self.realm {
             Some(ref realm) => {
                 let mut realm = realm.lock().unwrap();
+                let created = !is_registered(&realm, &procedure, options.pattern_match);
                 let manager = &mut realm.registration_manager;
                 let procedure_id = {
                     let procedure_id = match manager.registrations.register_with(
@@ -52,7 +59,13 @@ impl ConnectionHandler {
                         options.pattern_match == MatchingPolicy::Prefix,
                     ),
                 );
-                send_message(&self.info, &Message::Registered(request_id, procedure_id))
+                try!(send_message(
+                    &self.info,
+                    &Message::Registered(request_id, procedure_id)
+                ));
+                let my_id = self.info.lock().unwrap().id;
+                on_register(&realm, my_id, procedure_id, created);
+                Ok(())
             }
             None => Err(Error::new(ErrorKind::InvalidState(
                 "Received a message while not attached to a realm",
@@ -92,7 +105,13 @@ impl ConnectionHandler {
                     }
                 };
                 self.registered_procedures.retain(|id| *id != procedure_id);
-                send_message(&self.info, &Message::Unregistered(request_id))
+                try!(send_message(
+                    &self.info,
+                    &Message::Unregistered(request_id)
+                ));
+                let my_id = self.info.lock().unwrap().id;
+                on_unregister(&realm, my_id, procedure_id);
+                Ok(())
             }
             None => Err(Error::new(ErrorKind::InvalidState(
                 "Received a message while not attached to a realm",
@@ -103,18 +122,28 @@ impl ConnectionHandler {
     pub fn handle_call(
         &mut self,
         request_id: ID,
-        _options: CallOptions,
+        options: CallOptions,
         procedure: URI,
         args: Option<List>,
         kwargs: Option<Dict>,
     ) -> WampResult<()> {
+        if is_meta_procedure(&procedure) {
+            return self.handle_meta_call(request_id, procedure, args, kwargs);
+        }
         debug!(
             "Responding to call message (id: {}, procedure: {})",
             request_id, procedure.uri
         );
         match self.realm {
-            Some(ref realm) => {
-                let mut realm = realm.lock().unwrap();
+            Some(ref realm_handle) => {
+                let mut realm = realm_handle.lock().unwrap();
+                if options.disclose_me && !realm.allow_caller_disclosure {
+                    return Err(Error::new(ErrorKind::ErrorReason(
+                        ErrorType::Call,
+                        request_id,
+                        Reason::OptionDisallowedDiscloseMe,
+                    )));
+                }
                 let manager = &mut realm.registration_manager;
                 let invocation_id = random_id();
                 info!("Current procedure tree: {:?}", manager.registrations);
@@ -129,18 +158,42 @@ impl ConnectionHandler {
                             )))
                         }
                     };
-                manager
-                    .active_calls
-                    .insert(invocation_id, (request_id, Arc::clone(&self.info)));
+                manager.active_calls.insert(
+                    invocation_id,
+                    ActiveCall {
+                        call_id: request_id,
+                        caller: Arc::clone(&self.info),
+                        callee: Arc::clone(registrant),
+                        receive_progress: options.receive_progress,
+                    },
+                );
                 let mut details = InvocationDetails::new();
                 details.procedure = if policy == MatchingPolicy::Strict {
                     None
                 } else {
                     Some(procedure)
                 };
+                details.receive_progress = options.receive_progress;
+                details.ppt_scheme = options.ppt_scheme;
+                details.ppt_serializer = options.ppt_serializer;
+                details.ppt_cipher = options.ppt_cipher;
+                details.ppt_keyid = options.ppt_keyid;
+                if options.disclose_me {
+                    let info = self.info.lock().unwrap();
+                    details.caller = Some(info.id);
+                    details.caller_authid = info.authid.clone();
+                    details.caller_authrole = info.authrole.clone();
+                }
                 let invocation_message =
                     Message::Invocation(invocation_id, procedure_id, details, args, kwargs);
                 try!(send_message(registrant, &invocation_message));
+                if options.timeout > 0 {
+                    self.router.call_timer.enforce(
+                        Arc::clone(realm_handle),
+                        invocation_id,
+                        options.timeout,
+                    );
+                }
 
                 Ok(())
             }
@@ -153,7 +206,7 @@ impl ConnectionHandler {
     pub fn handle_yield(
         &mut self,
         invocation_id: ID,
-        _options: YieldOptions,
+        options: YieldOptions,
         args: Option<List>,
         kwargs: Option<Dict>,
     ) -> WampResult<()> {
@@ -162,14 +215,109 @@ impl ConnectionHandler {
             Some(ref realm) => {
                 let mut realm = realm.lock().unwrap();
                 let manager = &mut realm.registration_manager;
-                if let Some((call_id, callee)) = manager.active_calls.remove(&invocation_id) {
-                    let result_message =
-                        Message::Result(call_id, ResultDetails::new(), args, kwargs);
-                    send_message(&callee, &result_message)
+                if let Some(pending) = manager.pending_authorizations.remove(&invocation_id) {
+                    pending.resolve(is_allowed(&args));
+                    return Ok(());
+                }
+                if let Some(pending) = manager.pending_authentications.remove(&invocation_id) {
+                    pending.accept(&args);
+                    return Ok(());
+                }
+                let mut details = ResultDetails::new();
+                details.progress = options.progress;
+                details.ppt_scheme = options.ppt_scheme;
+                details.ppt_serializer = options.ppt_serializer;
+                details.ppt_cipher = options.ppt_cipher;
+                details.ppt_keyid = options.ppt_keyid;
+                if options.progress {
+                    // Progressive results leave the call open until the final yield arrives
+                    match manager.active_calls.get(&invocation_id) {
+                        Some(call) => {
+                            if call.receive_progress {
+                                let result_message =
+                                    Message::Result(call.call_id, details, args, kwargs);
+                                send_message(&call.caller, &result_message)
+                            } else {
+                                debug!(
+                                    "Discarding progressive result for a call that did not request it (id: {})",
+                                    invocation_id
+                                );
+                                Ok(())
+                            }
+                        }
+                        None => {
+                            debug!(
+                                "Discarding progressive result for a call that is no longer active (id: {})",
+                                invocation_id
+                            );
+                            Ok(())
+                        }
+                    }
+                } else if let Some(call) = manager.active_calls.remove(&invocation_id) {
+                    let result_message = Message::Result(call.call_id, details, args, kwargs);
+                    send_message(&call.caller, &result_message)
+                } else {
+                    // The call may have been cancelled while the callee was working on it
+                    debug!(
+                        "Discarding result for a call that is no longer active (id: {})",
+                        invocation_id
+                    );
+                    Ok(())
+                }
+            }
+            None => Err(Error::new(ErrorKind::InvalidState(
+                "Received a message while not attached to a realm",
+            ))),
+        }
+    }
+
+    pub fn handle_cancel(&mut self, request_id: ID, options: CancelOptions) -> WampResult<()> {
+        debug!(
+            "Responding to cancel message (id: {}, mode: {:?})",
+            request_id, options.mode
+        );
+        match self.realm {
+            Some(ref realm) => {
+                let mut realm = realm.lock().unwrap();
+                let manager = &mut realm.registration_manager;
+                let invocation_id = match manager
+                    .active_calls
+                    .iter()
+                    .find(|&(_, call)| {
+                        call.call_id == request_id && Arc::ptr_eq(&call.caller, &self.info)
+                    })
+                    .map(|(invocation_id, _)| *invocation_id)
+                {
+                    Some(invocation_id) => invocation_id,
+                    None => {
+                        // The call has already completed, so there is nothing to cancel
+                        debug!("Ignoring cancel for a call that is not active");
+                        return Ok(());
+                    }
+                };
+                if options.mode != CancelMode::Skip {
+                    let callee = Arc::clone(&manager.active_calls[&invocation_id].callee);
+                    try!(send_message(
+                        &callee,
+                        &Message::Interrupt(invocation_id, InterruptOptions::new(options.mode))
+                    ));
+                }
+                if options.mode == CancelMode::Kill {
+                    // Wait for the callee to respond with an error, which is passed on to the caller
+                    Ok(())
                 } else {
-                    Err(Error::new(ErrorKind::InvalidState(
-                        "Received a yield message for a call that wasn't sent",
-                    )))
+                    manager.active_calls.remove(&invocation_id);
+                    send_message(
+                        &self.info,
+                        &Message::Error(
+                            ErrorType::Call,
+                            request_id,
+                            HashMap::new(),
+                            Reason::Cancelled,
+                            None,
+                            None,
+                        ),
+                    )
                 }
             }
             None => Err(Error::new(ErrorKind::InvalidState(
@@ -184,13 +184,63 @@ impl<P: PatternData> RegistrationPatternNode<P> {
         &self,
         procedure: URI,
     ) -> Result<(&P, ID, MatchingPolicy), PatternError> {
-        let wrapper = self.find_registrant(&procedure.uri.split('.').collect::<Vec<&str>>(), 0);
+        let wrapper = self.find_registrant(
+            &procedure.uri.split('.').collect::<Vec<&str>>(),
+            0,
+            &ProcdureCollection::get_entry,
+        );
         match wrapper {
             Some((data, id)) => Ok((&data.registrant, id, data.policy)),
             None => Err(PatternError::new(Reason::NoSuchProcedure)),
         }
     }
 
+    /// Gets the id and matching policy of the registration that a call to the given uri would
+    /// be routed to, without selecting a registrant.
+    pub fn match_registration(&self, procedure: &URI) -> Option<(ID, MatchingPolicy)> {
+        let wrapper = self.find_registrant(
+            &procedure.uri.split('.').collect::<Vec<&str>>(),
+            0,
+            &|collection: &ProcdureCollection<P>| collection.procedures.first(),
+        );
+        wrapper.map(|(data, id)| (id, data.policy))
+    }
+
+    /// Gets the registration with exactly the given pattern, if it has any registrants.
+    ///
+    /// Returns the id, matching policy and invocation policy of the registration, along with its
+    /// registrants.
+    pub fn get_registration(
+        &self,
+        procedure: &str,
+        is_prefix: bool,
+    ) -> Option<(ID, MatchingPolicy, InvocationPolicy, Vec<&P>)> {
+        let mut node = self;
+        for uri_bit in procedure.split('.') {
+            node = match node.edges.get(uri_bit) {
+                Some(edge) => edge,
+                None => return None,
+            };
+        }
+        let (collection, id) = if is_prefix {
+            (&node.prefix_connections, node.prefix_id)
+        } else {
+            (&node.connections, node.id)
+        };
+        collection.procedures.first().map(|first| {
+            (
+                id,
+                first.policy,
+                collection.invocation_policy,
+                collection
+                    .procedures
+                    .iter()
+                    .map(|wrapper| &wrapper.registrant)
+                    .collect(),
+            )
+        })
+    }
+
     /// Constructs a new RegistrationPatternNode to be used as the root of the trie
     #[inline]
     pub fn new() -> RegistrationPatternNode<P> {
@@ -281,32 +331,48 @@ impl<P: PatternData> RegistrationPatternNode<P> {
         }
     }
 
-    fn find_registrant(&self, uri_bits: &[&str], depth: usize) -> Option<(&DataWrapper<P>, ID)> {
+    fn find_registrant<'a, F>(
+        &'a self,
+        uri_bits: &[&str],
+        depth: usize,
+        select: &F,
+    ) -> Option<(&'a DataWrapper<P>, ID)>
+    where
+        F: Fn(&'a ProcdureCollection<P>) -> Option<&'a DataWrapper<P>>,
+    {
         if depth == uri_bits.len() {
-            if let Some(registrant) = self.connections.get_entry() {
+            if let Some(registrant) = select(&self.connections) {
                 Some((registrant, self.id))
-            } else if let Some(registrant) = self.prefix_connections.get_entry() {
+            } else if let Some(registrant) = select(&self.prefix_connections) {
                 Some((registrant, self.prefix_id))
             } else {
                 None
             }
-        } else if let Some((registrant, id)) = self.recurse(uri_bits, depth) {
+        } else if let Some((registrant, id)) = self.recurse(uri_bits, depth, select) {
             Some((registrant, id))
-        } else if let Some(registrant) = self.prefix_connections.get_entry() {
+        } else if let Some(registrant) = select(&self.prefix_connections) {
             Some((registrant, self.prefix_id))
         } else {
             None
         }
     }
 
-    fn recurse(&self, uri_bits: &[&str], depth: usize) -> Option<(&DataWrapper<P>, ID)> {
+    fn recurse<'a, F>(
+        &'a self,
+        uri_bits: &[&str],
+        depth: usize,
+        select: &F,
+    ) -> Option<(&'a DataWrapper<P>, ID)>
+    where
+        F: Fn(&'a ProcdureCollection<P>) -> Option<&'a DataWrapper<P>>,
+    {
         if let Some(edge) = self.edges.get(uri_bits[depth]) {
-            if let Some(registrant) = edge.find_registrant(uri_bits, depth + 1) {
+            if let Some(registrant) = edge.find_registrant(uri_bits, depth + 1, select) {
                 return Some(registrant);
             }
         }
         if let Some(edge) = self.edges.get("") {
-            if let Some(registrant) = edge.find_registrant(uri_bits, depth + 1) {
+            if let Some(registrant) = edge.find_registrant(uri_bits, depth + 1, select) {
                 return Some(registrant);
             }
         }
@@ -443,4 +509,61 @@ mod test {
             ids[1]
         );
     }
+
+    #[test]
+    fn inspecting_registrations() {
+        let connection1 = MockData::new(1);
+        let connection2 = MockData::new(2);
+        let connection3 = MockData::new(3);
+        let mut root = RegistrationPatternNode::new();
+
+        let ids = [
+            root.register_with(
+                &URI::new("com.example.test..topic"),
+                connection1.clone(),
+                MatchingPolicy::Wildcard,
+                InvocationPolicy::Single,
+            ).unwrap(),
+            root.register_with(
+                &URI::new("com.example"),
+                connection2,
+                MatchingPolicy::Prefix,
+                InvocationPolicy::RoundRobin,
+            ).unwrap(),
+            root.register_with(
+                &URI::new("com.example"),
+                connection3,
+                MatchingPolicy::Prefix,
+                InvocationPolicy::RoundRobin,
+            ).unwrap(),
+        ];
+
+        assert_eq!(
+            root.match_registration(&URI::new("com.example.test.specific.topic")),
+            Some((ids[0], MatchingPolicy::Wildcard))
+        );
+        assert_eq!(
+            root.match_registration(&URI::new("com.example.other")),
+            Some((ids[1], MatchingPolicy::Prefix))
+        );
+        assert_eq!(root.match_registration(&URI::new("org.example")), None);
+
+        let (id, policy, invocation_policy, registrants) =
+            root.get_registration("com.example", true).unwrap();
+        assert_eq!(id, ids[2]);
+        assert_eq!(policy, MatchingPolicy::Prefix);
+        assert_eq!(invocation_policy, InvocationPolicy::RoundRobin);
+        assert_eq!(
+            registrants.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(root.get_registration("com.example", false).is_none());
+
+        root.unregister_with("com.example.test..topic", &connection1, false)
+            .unwrap();
+        assert!(
+            root.get_registration("com.example.test..topic", false)
+                .is_none()
+        );
+    }
 }
@@ -0,0 +1,128 @@
+//! Enforces the timeouts callers set on their calls.  A single thread keeps the deadlines of all
+//! calls in a heap and sleeps until the earliest one, instead of one sleeping thread per call.
+use router::messaging::send_message;
+use router::Realm;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use messages::{CancelMode, ErrorType, InterruptOptions, Message, Reason};
+use ID;
+
+pub struct CallTimer {
+    deadlines: Mutex<Sender<CallDeadline>>,
+}
+
+struct CallDeadline {
+    deadline: Instant,
+    realm: Arc<Mutex<Realm>>,
+    invocation_id: ID,
+    timeout: u64,
+}
+
+impl CallTimer {
+    /// Starts the timer thread, which stops once the timer is dropped.
+    pub fn new() -> CallTimer {
+        let (sender, receiver) = channel();
+        thread::spawn(move || run(receiver));
+        CallTimer {
+            deadlines: Mutex::new(sender),
+        }
+    }
+
+    /// Cancels the given invocation if it is still active after `timeout` milliseconds.  The
+    /// callee is interrupted, and the caller receives a timeout error.
+    pub fn enforce(&self, realm: Arc<Mutex<Realm>>, invocation_id: ID, timeout: u64) {
+        let deadline = CallDeadline {
+            deadline: Instant::now() + Duration::from_millis(timeout),
+            realm: realm,
+            invocation_id: invocation_id,
+            timeout: timeout,
+        };
+        self.deadlines.lock().unwrap().send(deadline).ok();
+    }
+}
+
+fn run(receiver: Receiver<CallDeadline>) {
+    let mut deadlines = BinaryHeap::new();
+    loop {
+        let wait = match deadlines.peek() {
+            Some(&CallDeadline { deadline, .. }) => {
+                let now = Instant::now();
+                if deadline <= now {
+                    expire(deadlines.pop().unwrap());
+                    continue;
+                }
+                Some(deadline - now)
+            }
+            None => None,
+        };
+        let received = match wait {
+            Some(wait) => receiver.recv_timeout(wait),
+            None => receiver
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(deadline) => deadlines.push(deadline),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+fn expire(deadline: CallDeadline) {
+    let mut realm = deadline.realm.lock().unwrap();
+    if let Some(call) = realm
+        .registration_manager
+        .active_calls
+        .remove(&deadline.invocation_id)
+    {
+        debug!(
+            "Call timed out after {}ms (id: {})",
+            deadline.timeout, call.call_id
+        );
+        send_message(
+            &call.callee,
+            &Message::Interrupt(
+                deadline.invocation_id,
+                InterruptOptions::new(CancelMode::KillNoWait),
+            ),
+        ).ok();
+        send_message(
+            &call.caller,
+            &Message::Error(
+                ErrorType::Call,
+                call.call_id,
+                HashMap::new(),
+                Reason::Timeout,
+                None,
+                None,
+            ),
+        ).ok();
+    }
+}
+
+// The heap pops the greatest element first, so deadlines are ordered from latest to earliest.
+impl Ord for CallDeadline {
+    fn cmp(&self, other: &CallDeadline) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+impl PartialOrd for CallDeadline {
+    fn partial_cmp(&self, other: &CallDeadline) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for CallDeadline {
+    fn eq(&self, other: &CallDeadline) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for CallDeadline {}
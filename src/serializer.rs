@@ -0,0 +1,125 @@
+//! Contains the serializers that turn WAMP messages into transport messages and back.  Each one
+//! is named by the WebSocket subprotocol that announces it, and the router and client look them
+//! up by that name instead of comparing protocols at every send and receive.
+use messages::Message;
+use rmp_serde::Deserializer as RMPDeserializer;
+use rmp_serde::Serializer as RMPSerializer;
+use serde::{Deserialize, Serialize};
+use serde_cbor;
+use serde_json;
+use std::io::Cursor;
+use utils::StructMapWriter;
+use ws::Message as WSMessage;
+use {Error, ErrorKind, WampResult};
+
+pub trait Serializer: Sync {
+    /// The WebSocket subprotocol that announces this serializer, such as `wamp.2.json`.
+    fn protocol(&self) -> &'static str;
+
+    fn serialize(&self, message: &Message) -> WSMessage;
+
+    fn deserialize(&self, message: WSMessage) -> WampResult<Message>;
+}
+
+pub struct JsonSerializer;
+pub struct MsgPackSerializer;
+pub struct CborSerializer;
+
+static SERIALIZERS: [&'static Serializer; 3] =
+    [&MsgPackSerializer, &CborSerializer, &JsonSerializer];
+
+/// The supported serializers, in the order the client asks for them.
+pub fn all() -> &'static [&'static Serializer] {
+    &SERIALIZERS
+}
+
+/// Looks up the serializer announced by the given WebSocket subprotocol.
+pub fn from_protocol(protocol: &str) -> Option<&'static Serializer> {
+    SERIALIZERS
+        .iter()
+        .find(|serializer| serializer.protocol() == protocol)
+        .cloned()
+}
+
+impl Serializer for JsonSerializer {
+    fn protocol(&self) -> &'static str {
+        "wamp.2.json"
+    }
+
+    fn serialize(&self, message: &Message) -> WSMessage {
+        WSMessage::Text(serde_json::to_string(message).unwrap())
+    }
+
+    fn deserialize(&self, message: WSMessage) -> WampResult<Message> {
+        match serde_json::from_slice(&message.into_data()) {
+            Ok(message) => Ok(message),
+            Err(e) => Err(Error::new(ErrorKind::JSONError(e))),
+        }
+    }
+}
+
+impl Serializer for MsgPackSerializer {
+    fn protocol(&self) -> &'static str {
+        "wamp.2.msgpack"
+    }
+
+    fn serialize(&self, message: &Message) -> WSMessage {
+        let mut buf: Vec<u8> = Vec::new();
+        message
+            .serialize(&mut RMPSerializer::with(&mut buf, StructMapWriter))
+            .unwrap();
+        WSMessage::Binary(buf)
+    }
+
+    fn deserialize(&self, message: WSMessage) -> WampResult<Message> {
+        let mut de = RMPDeserializer::new(Cursor::new(message.into_data()));
+        match Deserialize::deserialize(&mut de) {
+            Ok(message) => Ok(message),
+            Err(e) => Err(Error::new(ErrorKind::MsgPackError(e))),
+        }
+    }
+}
+
+impl Serializer for CborSerializer {
+    fn protocol(&self) -> &'static str {
+        "wamp.2.cbor"
+    }
+
+    fn serialize(&self, message: &Message) -> WSMessage {
+        WSMessage::Binary(serde_cbor::to_vec(message).unwrap())
+    }
+
+    fn deserialize(&self, message: WSMessage) -> WampResult<Message> {
+        match serde_cbor::from_slice(&message.into_data()) {
+            Ok(message) => Ok(message),
+            Err(e) => Err(Error::new(ErrorKind::CBORError(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{all, from_protocol};
+    use messages::{Message, PublishOptions, Value, URI};
+    use std::collections::HashMap;
+
+    #[test]
+    fn round_tripping_messages() {
+        let mut kwargs = HashMap::new();
+        kwargs.insert("reading".to_string(), Value::Float(-4.5));
+        kwargs.insert("raw".to_string(), Value::Binary(vec![0, 255]));
+        let message = Message::Publish(
+            27,
+            PublishOptions::new(true),
+            URI::new("ca.dal.wamp.test.topic"),
+            Some(vec![Value::String("sensor".to_string())]),
+            Some(kwargs),
+        );
+        for serializer in all() {
+            let serialized = serializer.serialize(&message);
+            assert_eq!(serializer.deserialize(serialized).unwrap(), message);
+        }
+        assert_eq!(from_protocol("wamp.2.cbor").unwrap().protocol(), "wamp.2.cbor");
+        assert!(from_protocol("wamp.2.ubjson").is_none());
+    }
+}
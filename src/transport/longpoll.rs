@@ -0,0 +1,544 @@
+//! Contains the HTTP long-poll transport, which carries WAMP messages over plain HTTP requests for
+//! clients behind proxies that do not pass WebSocket upgrades through.
+//!
+//! A client opens a transport with a request to `<base>/open`, which answers with the id of the
+//! new transport.  After that, the client posts each message to `<base>/<id>/send`, and keeps a
+//! request to `<base>/<id>/receive` open to get the messages for it, one per response.  A request
+//! to `<base>/<id>/close` ends the transport.  The serializer is the first of the protocols offered
+//! in the open request that the router supports.  Messages in a binary serialization are carried
+//! as raw request and response bodies.
+use super::{schedule_timeout, Event, Transport};
+use auth;
+use hex;
+use httparse;
+use serde_json;
+use serializer::{self, JsonSerializer, Serializer};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender as ChannelSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use ws::util::Token;
+use ws::{CloseCode, Message as WSMessage, Result as WSResult};
+
+/// How long a receive request waits for a message before answering with no content.
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// How long a transport lives without hearing from its client.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a client may take to send the head of a request.
+const HEAD_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client may stall while sending the body of a request.
+const BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many requests are served at once.  Each one holds a thread, receive requests for as long as
+/// `POLL_TIMEOUT`, so further requests are refused until some are answered.
+const MAX_REQUESTS: usize = 512;
+
+const MAX_HEADER_LENGTH: usize = 8 * 1024;
+const MAX_BODY_LENGTH: usize = 16 * 1024 * 1024;
+
+const JSON_CONTENT: &str = "application/json; charset=utf-8";
+const BINARY_CONTENT: &str = "application/octet-stream";
+
+type Sessions = Arc<Mutex<HashMap<String, LongPollSender>>>;
+
+type Reply = (&'static str, &'static str, Vec<u8>);
+
+/// The sending half of a long-poll transport.  Messages wait in a queue until the client asks for
+/// them.
+#[derive(Clone)]
+pub struct LongPollSender {
+    outgoing: Arc<(Mutex<Outgoing>, Condvar)>,
+    events: ChannelSender<Event>,
+    binary: bool,
+}
+
+struct Outgoing {
+    messages: VecDeque<Vec<u8>>,
+    closed: bool,
+    last_seen: Instant,
+}
+
+enum Poll {
+    Message(Vec<u8>),
+    Empty,
+    Closed,
+}
+
+/// Counts a request among those being served, until it is dropped.
+struct RequestSlot(Arc<AtomicUsize>);
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct OpenRequest {
+    protocols: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct OpenResponse {
+    protocol: &'static str,
+    transport: String,
+}
+
+/// Serves long-poll transports to the clients connecting to the given listener.  Each transport
+/// that is opened is passed to `open_session`, along with the events to pass to
+/// `transport::run()` and the serializer the client chose.  Never returns.
+pub fn serve<F>(listener: TcpListener, open_session: F)
+where
+    F: Fn(LongPollSender, Receiver<Event>, &'static Serializer) + Send + Sync + 'static,
+{
+    let sessions: Sessions = Arc::new(Mutex::new(HashMap::new()));
+    let open_session = Arc::new(open_session);
+    let active = Arc::new(AtomicUsize::new(0));
+    {
+        let sessions = Arc::clone(&sessions);
+        thread::spawn(move || expire_sessions(&sessions));
+    }
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let slot = match RequestSlot::take(&active) {
+                    Some(slot) => slot,
+                    None => {
+                        warn!("Refusing a long-poll request, since too many are being served");
+                        let body = b"Too many requests are being served";
+                        respond(&mut stream, "503 Service Unavailable", JSON_CONTENT, body).ok();
+                        continue;
+                    }
+                };
+                let sessions = Arc::clone(&sessions);
+                let open_session = Arc::clone(&open_session);
+                thread::spawn(move || {
+                    handle_request(stream, &sessions, &*open_session);
+                    drop(slot);
+                });
+            }
+            Err(e) => warn!("Could not accept long-poll connection: {}", e),
+        }
+    }
+}
+
+fn expire_sessions(sessions: &Sessions) {
+    loop {
+        thread::sleep(SESSION_TIMEOUT / 4);
+        sessions.lock().unwrap().retain(|id, sender| {
+            if sender.idle() < SESSION_TIMEOUT {
+                true
+            } else {
+                debug!("Closing long-poll transport {} after its client went away", id);
+                sender.shutdown().ok();
+                false
+            }
+        });
+    }
+}
+
+fn handle_request<F>(mut stream: TcpStream, sessions: &Sessions, open_session: &F)
+where
+    F: Fn(LongPollSender, Receiver<Event>, &'static Serializer),
+{
+    let (status, content_type, body) = match read_request(&mut stream) {
+        Ok(request) => route(request, sessions, open_session),
+        Err(details) => {
+            warn!("Received a malformed long-poll request: {}", details);
+            ("400 Bad Request", JSON_CONTENT, details.as_bytes().to_vec())
+        }
+    };
+    if let Err(e) = respond(&mut stream, status, content_type, &body) {
+        warn!("Could not answer long-poll request: {}", e);
+    }
+}
+
+/// Answers a request with its status, content type and body.
+fn route<F>(request: Request, sessions: &Sessions, open_session: &F) -> Reply
+where
+    F: Fn(LongPollSender, Receiver<Event>, &'static Serializer),
+{
+    let path = request.path.split('?').next().unwrap_or("");
+    let mut segments = path.trim_end_matches('/').rsplit('/');
+    let action = segments.next().unwrap_or("");
+    let transport_id = segments.next().unwrap_or("");
+    match &request.method[..] {
+        // Lets browsers on other origins use the transport
+        "OPTIONS" => return ("204 No Content", JSON_CONTENT, Vec::new()),
+        "POST" => (),
+        _ => return ("405 Method Not Allowed", JSON_CONTENT, Vec::new()),
+    }
+    if action == "open" {
+        return open(&request.body, sessions, open_session);
+    }
+    let sender = match sessions.lock().unwrap().get(transport_id) {
+        Some(sender) => sender.clone(),
+        None => return ("404 Not Found", JSON_CONTENT, b"Unknown transport".to_vec()),
+    };
+    match action {
+        "send" => {
+            sender.touch();
+            let message = if sender.binary {
+                WSMessage::Binary(request.body)
+            } else {
+                match String::from_utf8(request.body) {
+                    Ok(text) => WSMessage::Text(text),
+                    Err(_) => {
+                        return (
+                            "400 Bad Request",
+                            JSON_CONTENT,
+                            b"Messages must be valid UTF-8".to_vec(),
+                        )
+                    }
+                }
+            };
+            sender.events.send(Event::Message(message)).ok();
+            ("202 Accepted", JSON_CONTENT, Vec::new())
+        }
+        "receive" => match sender.poll() {
+            Poll::Message(message) => ("200 OK", sender.content_type(), message),
+            Poll::Empty => ("204 No Content", JSON_CONTENT, Vec::new()),
+            Poll::Closed => {
+                sessions.lock().unwrap().remove(transport_id);
+                ("410 Gone", JSON_CONTENT, Vec::new())
+            }
+        },
+        "close" => {
+            sender.shutdown().ok();
+            sessions.lock().unwrap().remove(transport_id);
+            ("202 Accepted", JSON_CONTENT, Vec::new())
+        }
+        _ => ("404 Not Found", JSON_CONTENT, Vec::new()),
+    }
+}
+
+fn open<F>(body: &[u8], sessions: &Sessions, open_session: &F) -> Reply
+where
+    F: Fn(LongPollSender, Receiver<Event>, &'static Serializer),
+{
+    let serializer = match serde_json::from_slice::<OpenRequest>(body) {
+        Ok(request) => request
+            .protocols
+            .iter()
+            .filter_map(|protocol| serializer::from_protocol(protocol))
+            .next(),
+        Err(_) => None,
+    };
+    let serializer = match serializer {
+        Some(serializer) => serializer,
+        None => {
+            return (
+                "400 Bad Request",
+                JSON_CONTENT,
+                b"The client did not offer a supported protocol".to_vec(),
+            )
+        }
+    };
+    let transport_id = hex::encode(auth::random_bytes(16));
+    let (events, receiver) = channel();
+    let sender = LongPollSender {
+        outgoing: Arc::new((
+            Mutex::new(Outgoing {
+                messages: VecDeque::new(),
+                closed: false,
+                last_seen: Instant::now(),
+            }),
+            Condvar::new(),
+        )),
+        events: events,
+        binary: serializer.protocol() != JsonSerializer.protocol(),
+    };
+    sessions
+        .lock()
+        .unwrap()
+        .insert(transport_id.clone(), sender.clone());
+    open_session(sender, receiver, serializer);
+    let response = OpenResponse {
+        protocol: serializer.protocol(),
+        transport: transport_id,
+    };
+    ("200 OK", JSON_CONTENT, serde_json::to_vec(&response).unwrap())
+}
+
+fn read_request(stream: &mut TcpStream) -> Result<Request, &'static str> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    // The head must arrive in time as a whole, so a client can not hold a thread by trickling it
+    let deadline = Instant::now() + HEAD_TIMEOUT;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err("The request headers took too long to arrive");
+        }
+        try!(
+            stream
+                .set_read_timeout(Some(deadline - now))
+                .map_err(|_| "Could not read the request")
+        );
+        let read = try!(
+            stream
+                .read(&mut chunk)
+                .map_err(|_| "Could not read the request")
+        );
+        if read == 0 {
+            return Err("The connection closed before the request was complete");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some((method, path, header_length, body_length)) = try!(parse_head(&buffer)) {
+            if body_length > MAX_BODY_LENGTH {
+                return Err("The request is too long");
+            }
+            let mut body = buffer.split_off(header_length);
+            if body.len() < body_length {
+                let start = body.len();
+                body.resize(body_length, 0);
+                try!(
+                    stream
+                        .set_read_timeout(Some(BODY_TIMEOUT))
+                        .map_err(|_| "Could not read the request body")
+                );
+                try!(
+                    stream
+                        .read_exact(&mut body[start..])
+                        .map_err(|_| "Could not read the request body")
+                );
+            }
+            body.truncate(body_length);
+            return Ok(Request {
+                method: method,
+                path: path,
+                body: body,
+            });
+        }
+        if buffer.len() > MAX_HEADER_LENGTH {
+            return Err("The request headers are too long");
+        }
+    }
+}
+
+/// Parses the method, path and the lengths of the headers and body of a request, if all of the
+/// headers have arrived.
+fn parse_head(buffer: &[u8]) -> Result<Option<(String, String, usize, usize)>, &'static str> {
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut request = httparse::Request::new(&mut headers);
+    let header_length = match request.parse(buffer) {
+        Ok(httparse::Status::Complete(header_length)) => header_length,
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(_) => return Err("The request is not valid HTTP"),
+    };
+    let mut body_length = 0;
+    for header in request.headers.iter() {
+        if header.name.eq_ignore_ascii_case("content-length") {
+            body_length = match str::from_utf8(header.value)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+            {
+                Some(length) => length,
+                None => return Err("The request has an invalid Content-Length"),
+            };
+        }
+    }
+    Ok(Some((
+        request.method.unwrap_or("").to_string(),
+        request.path.unwrap_or("").to_string(),
+        header_length,
+        body_length,
+    )))
+}
+
+impl RequestSlot {
+    /// Takes one of the `MAX_REQUESTS` slots, if any is free.
+    fn take(active: &Arc<AtomicUsize>) -> Option<RequestSlot> {
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_REQUESTS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(RequestSlot(Arc::clone(active)))
+    }
+}
+
+impl Drop for RequestSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> WSResult<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: POST, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Content-Type\r\n\
+         Cache-Control: no-store\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    try!(stream.write_all(head.as_bytes()));
+    try!(stream.write_all(body));
+    Ok(())
+}
+
+impl LongPollSender {
+    fn touch(&self) {
+        self.outgoing.0.lock().unwrap().last_seen = Instant::now();
+    }
+
+    fn idle(&self) -> Duration {
+        self.outgoing.0.lock().unwrap().last_seen.elapsed()
+    }
+
+    fn content_type(&self) -> &'static str {
+        if self.binary {
+            BINARY_CONTENT
+        } else {
+            JSON_CONTENT
+        }
+    }
+
+    /// Waits for the next message to the client.  Messages that were queued before the transport
+    /// closed are still handed out.
+    fn poll(&self) -> Poll {
+        let (ref lock, ref ready) = *self.outgoing;
+        let mut outgoing = lock.lock().unwrap();
+        let start = Instant::now();
+        let poll = loop {
+            if let Some(message) = outgoing.messages.pop_front() {
+                break Poll::Message(message);
+            }
+            if outgoing.closed {
+                break Poll::Closed;
+            }
+            let waited = start.elapsed();
+            if waited >= POLL_TIMEOUT {
+                break Poll::Empty;
+            }
+            outgoing = ready.wait_timeout(outgoing, POLL_TIMEOUT - waited).unwrap().0;
+        };
+        outgoing.last_seen = Instant::now();
+        poll
+    }
+}
+
+impl Transport for LongPollSender {
+    fn send(&self, message: WSMessage) -> WSResult<()> {
+        let (ref lock, ref ready) = *self.outgoing;
+        lock.lock().unwrap().messages.push_back(message.into_data());
+        ready.notify_all();
+        Ok(())
+    }
+
+    fn close(&self, _code: CloseCode) -> WSResult<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&self) -> WSResult<()> {
+        // The queued messages are kept, since the client may still be about to receive a goodbye
+        let (ref lock, ref ready) = *self.outgoing;
+        lock.lock().unwrap().closed = true;
+        ready.notify_all();
+        self.events.send(Event::Closed).ok();
+        Ok(())
+    }
+
+    fn timeout(&self, ms: u64, token: Token) -> WSResult<()> {
+        schedule_timeout(&self.events, ms, token);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve, RequestSlot, MAX_REQUESTS};
+    use serde_json::{self, Value as JsonValue};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use transport::{Event, Transport};
+
+    fn post(address: SocketAddr, path: &str, body: &str) -> (u32, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            body.len(),
+            body
+        ).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap().to_string();
+        (status, body)
+    }
+
+    #[test]
+    fn exchanging_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            serve(listener, |sender, events, _| {
+                thread::spawn(move || {
+                    for event in events.iter() {
+                        match event {
+                            Event::Message(message) => sender.send(message).unwrap(),
+                            Event::Timeout(_) => (),
+                            Event::Closed => break,
+                        }
+                    }
+                });
+            })
+        });
+
+        let (status, _) = post(address, "/lp/open", r#"{"protocols":["wamp.2.ubjson"]}"#);
+        assert_eq!(status, 400);
+        let offered = r#"{"protocols":["wamp.2.ubjson","wamp.2.cbor","wamp.2.json"]}"#;
+        let (status, body) = post(address, "/lp/open", offered);
+        assert_eq!(status, 200);
+        let opened: JsonValue = serde_json::from_str(&body).unwrap();
+        assert_eq!(opened["protocol"], "wamp.2.cbor");
+        let (status, body) = post(address, "/lp/open", r#"{"protocols":["wamp.2.json"]}"#);
+        assert_eq!(status, 200);
+        let opened: JsonValue = serde_json::from_str(&body).unwrap();
+        assert_eq!(opened["protocol"], "wamp.2.json");
+        let transport = opened["transport"].as_str().unwrap();
+
+        let send = format!("/lp/{}/send", transport);
+        let receive = format!("/lp/{}/receive", transport);
+        assert_eq!(post(address, &send, "[1]").0, 202);
+        assert_eq!(post(address, &send, "[2]").0, 202);
+        assert_eq!(post(address, &receive, ""), (200, "[1]".to_string()));
+        assert_eq!(post(address, &receive, ""), (200, "[2]".to_string()));
+
+        assert_eq!(post(address, &format!("/lp/{}/close", transport), "").0, 202);
+        assert_eq!(post(address, &receive, "").0, 404);
+        assert_eq!(post(address, "/lp/unknown/send", "[1]").0, 404);
+    }
+
+    #[test]
+    fn limiting_the_requests_served_at_once() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let mut slots: Vec<_> = (0..MAX_REQUESTS)
+            .map(|_| RequestSlot::take(&active).unwrap())
+            .collect();
+        assert!(RequestSlot::take(&active).is_none());
+        assert_eq!(active.load(Ordering::SeqCst), MAX_REQUESTS);
+        slots.pop();
+        assert!(RequestSlot::take(&active).is_some());
+        slots.clear();
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,163 @@
+//! Contains the transports that carry WAMP messages between peers.
+pub mod longpoll;
+pub mod rawsocket;
+
+use std::sync::mpsc::{Receiver, Sender as ChannelSender};
+use std::thread;
+use std::time::Duration;
+use ws::util::Token;
+use ws::{CloseCode, Handler, Message as WSMessage, Result as WSResult, Sender};
+
+/// The sending half of a connection.  The router and the client hand it serialized messages
+/// without knowing which transport carries them.
+pub trait Transport: Send {
+    /// Sends a serialized message.  Text messages hold JSON, and binary messages hold whichever
+    /// binary serializer the connection negotiated.
+    fn send(&self, message: WSMessage) -> WSResult<()>;
+
+    /// Closes the connection once the messages that have already been sent are delivered.
+    fn close(&self, code: CloseCode) -> WSResult<()>;
+
+    /// Stops the connection immediately.
+    fn shutdown(&self) -> WSResult<()>;
+
+    /// Calls `on_timeout()` on the handler of the connection with the given token once the given
+    /// number of milliseconds have passed.  The handler is called from the same thread as its
+    /// other callbacks.
+    fn timeout(&self, ms: u64, token: Token) -> WSResult<()>;
+}
+
+impl Transport for Sender {
+    fn send(&self, message: WSMessage) -> WSResult<()> {
+        Sender::send(self, message)
+    }
+
+    fn close(&self, code: CloseCode) -> WSResult<()> {
+        Sender::close(self, code)
+    }
+
+    fn shutdown(&self) -> WSResult<()> {
+        Sender::shutdown(self)
+    }
+
+    fn timeout(&self, ms: u64, token: Token) -> WSResult<()> {
+        Sender::timeout(self, ms, token)
+    }
+}
+
+/// Something that happened on a connection that runs outside of the WebSocket event loop, to be
+/// passed to its handler.
+pub enum Event {
+    Message(WSMessage),
+    Timeout(Token),
+    Closed,
+}
+
+/// Passes the events of a connection to its handler until the connection closes, in the same way
+/// the WebSocket event loop does.
+pub fn run<H: Handler>(handler: &mut H, events: Receiver<Event>, sender: &Transport) {
+    for event in events.iter() {
+        let result = match event {
+            Event::Message(message) => handler.on_message(message),
+            Event::Timeout(token) => handler.on_timeout(token),
+            Event::Closed => break,
+        };
+        if let Err(e) = result {
+            error!("Closing connection after error: {}", e);
+            break;
+        }
+    }
+    sender.shutdown().ok();
+    handler.on_close(CloseCode::Normal, "");
+}
+
+/// Queues a timeout event once the given number of milliseconds have passed.
+fn schedule_timeout(events: &ChannelSender<Event>, ms: u64, token: Token) {
+    if ms == 0 {
+        events.send(Event::Timeout(token)).ok();
+    } else {
+        let events = events.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(ms));
+            events.send(Event::Timeout(token)).ok();
+        });
+    }
+}
+
+/// A transport that keeps the messages sent on it in place of a connection, shared by the tests
+/// of the router and the client.
+#[cfg(test)]
+pub mod testing {
+    use super::Transport;
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::time::{Duration, Instant};
+    use ws::util::Token;
+    use ws::{CloseCode, Message as WSMessage, Result as WSResult};
+
+    /// Records the text of every message sent.  Clones share the same record.
+    #[derive(Clone)]
+    pub struct Recorder {
+        sent: Arc<(Mutex<Vec<String>>, Condvar)>,
+    }
+
+    impl Recorder {
+        pub fn new() -> Recorder {
+            Recorder {
+                sent: Arc::new((Mutex::new(Vec::new()), Condvar::new())),
+            }
+        }
+
+        /// The messages sent so far, in the order they were sent.
+        pub fn sent(&self) -> Vec<String> {
+            (self.sent.0).lock().unwrap().clone()
+        }
+
+        /// Counts the messages sent so far that start with the given prefix.
+        pub fn count(&self, prefix: &str) -> usize {
+            count(&(self.sent.0).lock().unwrap(), prefix)
+        }
+
+        /// Waits until at least `expected` messages starting with the given prefix have been
+        /// sent, returning whether they were sent before the timeout.
+        pub fn wait_for(&self, prefix: &str, expected: usize, timeout: Duration) -> bool {
+            let (ref lock, ref sent_one) = *self.sent;
+            let deadline = Instant::now() + timeout;
+            let mut sent = lock.lock().unwrap();
+            while count(&sent, prefix) < expected {
+                let now = Instant::now();
+                if now >= deadline {
+                    return false;
+                }
+                sent = sent_one.wait_timeout(sent, deadline - now).unwrap().0;
+            }
+            true
+        }
+    }
+
+    fn count(sent: &[String], prefix: &str) -> usize {
+        sent.iter()
+            .filter(|message| message.starts_with(prefix))
+            .count()
+    }
+
+    impl Transport for Recorder {
+        fn send(&self, message: WSMessage) -> WSResult<()> {
+            let (ref lock, ref sent_one) = *self.sent;
+            lock.lock().unwrap().push(message.into_text().unwrap());
+            sent_one.notify_all();
+            Ok(())
+        }
+
+        fn close(&self, _code: CloseCode) -> WSResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self) -> WSResult<()> {
+            Ok(())
+        }
+
+        fn timeout(&self, _ms: u64, _token: Token) -> WSResult<()> {
+            Ok(())
+        }
+    }
+}
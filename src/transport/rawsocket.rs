@@ -0,0 +1,367 @@
+//! Contains the RawSocket transport, which carries WAMP messages over a plain TCP connection
+//! using length-prefixed frames instead of an HTTP upgrade and WebSocket framing.
+//!
+//! A connection starts with a four byte handshake in each direction.  The client asks for a
+//! serializer and announces the longest message it accepts, and the router answers with the
+//! same serializer and its own limit, or with an error code.  Every message after that is a frame
+//! with a four byte header, holding the frame type and the 24 bit length of the payload.
+//!
+//! The frames can be carried by a TCP connection or, on Unix, by a Unix domain socket.
+use super::{schedule_timeout, Event, Transport};
+use serializer::{CborSerializer, JsonSerializer, MsgPackSerializer, Serializer};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{channel, Receiver, Sender as ChannelSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use ws::util::Token;
+use ws::{CloseCode, Error as WSError, ErrorKind as WSErrorKind, Message as WSMessage,
+         Result as WSResult};
+
+const MAGIC: u8 = 0x7F;
+
+/// The longest message we accept, as an exponent of two on top of the minimum of 512 bytes.  15
+/// is the largest exponent, allowing messages of up to 16 MiB.
+const MAX_LENGTH_EXPONENT: u8 = 15;
+
+const FRAME_MESSAGE: u8 = 0;
+const FRAME_PING: u8 = 1;
+const FRAME_PONG: u8 = 2;
+
+const ERROR_SERIALIZER_UNSUPPORTED: u8 = 1;
+const ERROR_LENGTH_UNACCEPTABLE: u8 = 2;
+const ERROR_RESERVED_BITS: u8 = 3;
+const ERROR_CONNECTION_COUNT: u8 = 4;
+
+/// A stream of bytes that can carry a RawSocket connection.
+pub trait Socket: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+
+    /// Closes both directions of the stream.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+/// The serialization formats a RawSocket connection can negotiate, numbered as in the handshake.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RawSocketFormat {
+    Json = 1,
+    MsgPack = 2,
+    Cbor = 3,
+}
+
+/// The sending half of a RawSocket connection.
+pub struct RawSocketSender<S: Socket> {
+    stream: Arc<Mutex<S>>,
+    max_length: usize,
+    events: ChannelSender<Event>,
+}
+
+impl Socket for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn try_clone(&self) -> io::Result<UnixStream> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+// Deriving Clone would needlessly require the socket to be Clone
+impl<S: Socket> Clone for RawSocketSender<S> {
+    fn clone(&self) -> RawSocketSender<S> {
+        RawSocketSender {
+            stream: Arc::clone(&self.stream),
+            max_length: self.max_length,
+            events: self.events.clone(),
+        }
+    }
+}
+
+impl RawSocketFormat {
+    fn from_id(id: u8) -> Option<RawSocketFormat> {
+        match id {
+            1 => Some(RawSocketFormat::Json),
+            2 => Some(RawSocketFormat::MsgPack),
+            3 => Some(RawSocketFormat::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Finds the RawSocket format for the serializer announced by the given WebSocket subprotocol.
+    pub fn from_protocol(protocol: &str) -> Option<RawSocketFormat> {
+        [RawSocketFormat::Json, RawSocketFormat::MsgPack, RawSocketFormat::Cbor]
+            .iter()
+            .find(|format| format.message_serializer().protocol() == protocol)
+            .cloned()
+    }
+
+    /// The serializer for the messages of the connection.
+    pub fn message_serializer(&self) -> &'static Serializer {
+        match *self {
+            RawSocketFormat::Json => &JsonSerializer,
+            RawSocketFormat::MsgPack => &MsgPackSerializer,
+            RawSocketFormat::Cbor => &CborSerializer,
+        }
+    }
+}
+
+fn max_length(exponent: u8) -> usize {
+    1 << (9 + exponent as usize)
+}
+
+fn protocol_error(details: &'static str) -> WSError {
+    WSError::new(WSErrorKind::Protocol, details)
+}
+
+/// Answers the handshake a client opens a connection with.  Returns the serializer the client
+/// asked for and the longest message the client accepts.
+pub fn accept<S: Socket>(stream: &mut S) -> WSResult<(RawSocketFormat, usize)> {
+    let mut request = [0u8; 4];
+    try!(stream.read_exact(&mut request));
+    if request[0] != MAGIC {
+        return Err(protocol_error("The client did not open a RawSocket connection"));
+    }
+    if request[2] != 0 || request[3] != 0 {
+        try!(stream.write_all(&[MAGIC, ERROR_RESERVED_BITS << 4, 0, 0]));
+        return Err(protocol_error("The client used reserved bits of the handshake"));
+    }
+    let format = match RawSocketFormat::from_id(request[1] & 0x0F) {
+        Some(format) => format,
+        None => {
+            try!(stream.write_all(&[MAGIC, ERROR_SERIALIZER_UNSUPPORTED << 4, 0, 0]));
+            return Err(protocol_error("The client asked for an unsupported serializer"));
+        }
+    };
+    try!(stream.write_all(&[
+        MAGIC,
+        (MAX_LENGTH_EXPONENT << 4) | format as u8,
+        0,
+        0,
+    ]));
+    Ok((format, max_length(request[1] >> 4)))
+}
+
+/// Opens the handshake with the router, asking for the given serializer.  Returns the longest
+/// message the router accepts.
+pub fn connect<S: Socket>(stream: &mut S, format: RawSocketFormat) -> WSResult<usize> {
+    try!(stream.write_all(&[
+        MAGIC,
+        (MAX_LENGTH_EXPONENT << 4) | format as u8,
+        0,
+        0,
+    ]));
+    let mut reply = [0u8; 4];
+    try!(stream.read_exact(&mut reply));
+    if reply[0] != MAGIC {
+        return Err(protocol_error("The router did not answer with a RawSocket handshake"));
+    }
+    if reply[1] & 0x0F == format as u8 {
+        return Ok(max_length(reply[1] >> 4));
+    }
+    Err(protocol_error(match reply[1] >> 4 {
+        ERROR_SERIALIZER_UNSUPPORTED => "The router does not support the serializer",
+        ERROR_LENGTH_UNACCEPTABLE => "The router does not accept the maximum message length",
+        ERROR_RESERVED_BITS => "The router does not accept the use of reserved bits",
+        ERROR_CONNECTION_COUNT => "The router has reached its maximum connection count",
+        _ => "The router answered with an unexpected serializer",
+    }))
+}
+
+/// Starts reading frames from a connection whose handshake is complete.  Returns the sending half
+/// of the connection, along with the events to pass to `transport::run()`.
+pub fn open<S: Socket>(
+    stream: S,
+    format: RawSocketFormat,
+    max_length: usize,
+) -> WSResult<(RawSocketSender<S>, Receiver<Event>)> {
+    let (events, receiver) = channel();
+    let reader = try!(Socket::try_clone(&stream));
+    let sender = RawSocketSender {
+        stream: Arc::new(Mutex::new(stream)),
+        max_length: max_length,
+        events: events,
+    };
+    let reply_sender = sender.clone();
+    thread::spawn(move || read_frames(reader, format, reply_sender));
+    Ok((sender, receiver))
+}
+
+fn read_frames<S: Socket>(mut stream: S, format: RawSocketFormat, sender: RawSocketSender<S>) {
+    let limit = max_length(MAX_LENGTH_EXPONENT);
+    loop {
+        let mut header = [0u8; 4];
+        if stream.read_exact(&mut header).is_err() {
+            break;
+        }
+        let length =
+            (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+        if length > limit {
+            warn!("Received a RawSocket frame longer than we accept");
+            break;
+        }
+        let mut payload = vec![0u8; length];
+        if stream.read_exact(&mut payload).is_err() {
+            break;
+        }
+        match header[0] & 0x07 {
+            FRAME_MESSAGE => {
+                let message = if format == RawSocketFormat::Json {
+                    match String::from_utf8(payload) {
+                        Ok(text) => WSMessage::Text(text),
+                        Err(_) => {
+                            warn!("Received a JSON frame that is not valid UTF-8");
+                            break;
+                        }
+                    }
+                } else {
+                    WSMessage::Binary(payload)
+                };
+                if sender.events.send(Event::Message(message)).is_err() {
+                    break;
+                }
+            }
+            FRAME_PING => {
+                if sender.write_frame(FRAME_PONG, &payload).is_err() {
+                    break;
+                }
+            }
+            FRAME_PONG => trace!("Received RawSocket pong"),
+            _ => {
+                warn!("Received a RawSocket frame of unknown type {}", header[0]);
+                break;
+            }
+        }
+    }
+    sender.events.send(Event::Closed).ok();
+}
+
+impl<S: Socket> RawSocketSender<S> {
+    fn write_frame(&self, frame_type: u8, payload: &[u8]) -> WSResult<()> {
+        let length = payload.len();
+        if length > self.max_length || length > 0xFF_FFFF {
+            return Err(WSError::new(
+                WSErrorKind::Capacity,
+                "The message is longer than the peer accepts",
+            ));
+        }
+        let mut frame = Vec::with_capacity(length + 4);
+        frame.push(frame_type);
+        frame.push((length >> 16) as u8);
+        frame.push((length >> 8) as u8);
+        frame.push(length as u8);
+        frame.extend_from_slice(payload);
+        try!(self.stream.lock().unwrap().write_all(&frame));
+        Ok(())
+    }
+}
+
+impl<S: Socket> Transport for RawSocketSender<S> {
+    fn send(&self, message: WSMessage) -> WSResult<()> {
+        let payload = match message {
+            WSMessage::Text(text) => text.into_bytes(),
+            WSMessage::Binary(data) => data,
+        };
+        self.write_frame(FRAME_MESSAGE, &payload)
+    }
+
+    fn close(&self, _code: CloseCode) -> WSResult<()> {
+        // RawSocket has no closing handshake, since WAMP already says goodbye
+        self.shutdown()
+    }
+
+    fn shutdown(&self) -> WSResult<()> {
+        try!(Socket::shutdown(&*self.stream.lock().unwrap()));
+        Ok(())
+    }
+
+    fn timeout(&self, ms: u64, token: Token) -> WSResult<()> {
+        schedule_timeout(&self.events, ms, token);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{accept, connect, open, RawSocketFormat, FRAME_PING};
+    use std::net::{TcpListener, TcpStream};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use transport::{Event, Transport};
+    use ws::Message as WSMessage;
+
+    #[test]
+    fn exchanging_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (format, max_length) = accept(&mut stream).unwrap();
+            assert_eq!(format, RawSocketFormat::MsgPack);
+            assert_eq!(max_length, 1 << 24);
+            let (sender, events) = open(stream, format, max_length).unwrap();
+            match events.recv().unwrap() {
+                Event::Message(WSMessage::Binary(data)) => assert_eq!(data, vec![1, 2, 3]),
+                _ => panic!("Expected a binary message"),
+            }
+            sender.send(WSMessage::Binary(vec![4, 5])).unwrap();
+            match events.recv().unwrap() {
+                Event::Closed => (),
+                _ => panic!("Expected the connection to close"),
+            }
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let max_length = connect(&mut stream, RawSocketFormat::MsgPack).unwrap();
+        let (sender, events) = open(stream, RawSocketFormat::MsgPack, max_length).unwrap();
+        sender.write_frame(FRAME_PING, b"are you there").unwrap();
+        sender.send(WSMessage::Binary(vec![1, 2, 3])).unwrap();
+        match events.recv().unwrap() {
+            Event::Message(WSMessage::Binary(data)) => assert_eq!(data, vec![4, 5]),
+            _ => panic!("Expected a binary message"),
+        }
+        assert!(sender.send(WSMessage::Binary(vec![0; (1 << 24) + 1])).is_err());
+        sender.shutdown().unwrap();
+        router.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exchanging_frames_over_unix_socket() {
+        let (mut client, mut router) = UnixStream::pair().unwrap();
+        let router = thread::spawn(move || {
+            let (format, max_length) = accept(&mut router).unwrap();
+            assert_eq!(format, RawSocketFormat::Json);
+            let (sender, events) = open(router, format, max_length).unwrap();
+            match events.recv().unwrap() {
+                Event::Message(WSMessage::Text(text)) => {
+                    sender.send(WSMessage::Text(text)).unwrap()
+                }
+                _ => panic!("Expected a text message"),
+            }
+        });
+
+        let max_length = connect(&mut client, RawSocketFormat::Json).unwrap();
+        let (sender, events) = open(client, RawSocketFormat::Json, max_length).unwrap();
+        sender.send(WSMessage::Text("[1]".to_string())).unwrap();
+        match events.recv().unwrap() {
+            Event::Message(WSMessage::Text(text)) => assert_eq!(text, "[1]"),
+            _ => panic!("Expected a text message"),
+        }
+        router.join().unwrap();
+        sender.shutdown().unwrap();
+    }
+}